@@ -0,0 +1,168 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use pimp_my_axis::DeviceSource;
+use pimp_my_axis::cli::{get_event_devices, RealDevice};
+use pimp_my_axis::config::{Axis, AxisCalibration, parse_config, update_config_file};
+use pimp_my_axis::error::{ConfigError, DeviceError, Error, RuntimeError};
+
+/// Asks the user to move all absolute axes of the named real device through their full range and
+/// to center them, then writes the observed values to the calibration section of the device in
+/// the config file.
+pub fn calibrate(config_path: &Path, name: &str) -> Result<(), Error> {
+    let config = parse_config(config_path)?;
+    let mut device_config = match config.real_devices.get(name) {
+        Some(device_config) => device_config.clone(),
+        None => {
+            return Err(ConfigError::invalid(
+                format!("Real device '{}'", name),
+                "Not defined in the config file",
+            )
+            .into())
+        }
+    };
+    // The existing calibration must not be applied to the values being measured.
+    device_config.calibration.clear();
+    device_config.grab = false;
+
+    let mut device = RealDevice::new(name.to_owned(), &device_config);
+    device.connect(&get_event_devices()?)?;
+
+    let axes: Vec<Axis> = Axis::ABSOLUTE
+        .iter()
+        .copied()
+        .filter(|axis| device.supports(axis))
+        .collect();
+    if axes.is_empty() {
+        println!("Device '{}' has no absolute axes to calibrate.", name);
+        return Ok(());
+    }
+
+    let mut observed: HashMap<Axis, (i32, i32)> = HashMap::new();
+    for axis in &axes {
+        if let Ok(value) = device.read(axis) {
+            observed.insert(*axis, (value, value));
+        }
+    }
+
+    println!(
+        "Move every axis of '{}' through its full range a few times, then press Enter.",
+        name
+    );
+    record_until_enter(&mut device, &mut observed)?;
+
+    println!("Let all axes return to their resting position, then press Enter.");
+    record_until_enter(&mut device, &mut observed)?;
+
+    let mut calibrations = Vec::new();
+    for axis in &axes {
+        let (min, max) = match observed.get(axis) {
+            Some(&(min, max)) if min < max => (min, max),
+            _ => {
+                println!("{:<8} did not move, skipping it", axis.to_string());
+                continue;
+            }
+        };
+        let center = device.read(axis).ok();
+        // Pedals and throttles rest at one end, in which case there is no center to speak of.
+        let center = center.filter(|&center| min < center && center < max);
+
+        println!(
+            "{:<8} min {:>7}  center {:>7}  max {:>7}",
+            axis.to_string(),
+            min,
+            center.map_or_else(|| "-".to_owned(), |center| center.to_string()),
+            max
+        );
+        calibrations.push((*axis, AxisCalibration { min, center, max }));
+    }
+
+    for (axis, calibration) in &calibrations {
+        let axis = axis.to_string();
+        let keys = ["real_devices", name, "calibration", &axis];
+        if let Err(err) = update_config_file(config_path, &keys, &calibration_yaml(calibration)) {
+            println!(
+                "Unable to write the calibration to the config file: {}",
+                err
+            );
+            println!("Add it to the device yourself:");
+            println!("    calibration:");
+            for (axis, calibration) in &calibrations {
+                println!("      {}: {}", axis, calibration_yaml(calibration));
+            }
+            return Err(err.into());
+        }
+    }
+    println!("Wrote the calibration to {}", config_path.to_string_lossy());
+    return Ok(());
+}
+
+/// Widens the observed ranges by all axis values the device reports until the user presses Enter.
+fn record_until_enter(
+    device: &mut RealDevice,
+    observed: &mut HashMap<Axis, (i32, i32)>,
+) -> Result<(), Error> {
+    let disconnected = |device: &RealDevice| DeviceError::NotConnected {
+        device: device.name.clone(),
+    };
+    let device_fd = device.raw_fd().ok_or_else(|| disconnected(device))?;
+    loop {
+        let mut fds = [
+            PollFd::new(device_fd, PollFlags::POLLIN),
+            PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN),
+        ];
+        poll(&mut fds, -1).map_err(|err| RuntimeError::system("Unable to poll", err))?;
+        let readable = |fd: &PollFd| fd.revents().is_some_and(|revents| !revents.is_empty());
+
+        if readable(&fds[0]) {
+            let frames = device.read_frames().ok_or_else(|| disconnected(device))?;
+            for frame in frames {
+                for update in frame.axes {
+                    let (min, max) = observed
+                        .entry(update.axis)
+                        .or_insert((update.new_value, update.new_value));
+                    *min = (*min).min(update.new_value);
+                    *max = (*max).max(update.new_value);
+                }
+            }
+        }
+        if readable(&fds[1]) {
+            let mut line = String::new();
+            io::stdin()
+                .read_line(&mut line)
+                .map_err(RuntimeError::from)?;
+            return Ok(());
+        }
+    }
+}
+
+fn calibration_yaml(calibration: &AxisCalibration) -> String {
+    return match calibration.center {
+        Some(center) => format!(
+            "{{ min: {}, center: {}, max: {} }}",
+            calibration.min, center, calibration.max
+        ),
+        None => format!("{{ min: {}, max: {} }}", calibration.min, calibration.max),
+    };
+}
@@ -16,23 +16,318 @@
  */
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use evdev_rs::enums::{EV_KEY, EventCode, InputProp};
 use log::info;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde::de::{DeserializeOwned, Error, Unexpected};
+use serde_yaml::{Mapping, Value};
 
-use crate::expr::AxisExpression;
+use crate::error::ConfigError;
+use crate::expr::{AxisExpression, Operator};
+use crate::real::{event_code_name, event_codes, input_properties};
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum RealDeviceMatcher {
-    Path(PathBuf),
-    VendorAndProduct { vendor_id: u16, product_id: u16 },
+/// Criteria by which a real device is found. All given criteria must match.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RealDeviceMatcher {
+    pub path: Option<PathBuf>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    /// Exact evdev device name.
+    pub name: Option<String>,
+    pub name_regex: Option<Pattern>,
+    pub phys: Option<String>,
+    pub uniq: Option<String>,
+    pub bustype: Option<BusType>,
+    /// Exact values of udev properties, such as `ID_SERIAL` or `ID_INPUT_JOYSTICK`.
+    #[serde(default)]
+    pub udev_properties: HashMap<String, String>,
+    /// Pattern for the name of a link in `/dev/input/by-id` pointing to the device.
+    pub by_id: Option<Pattern>,
+    /// Exact udev `ID_PATH` of the device, which identifies the port it is plugged into.
+    pub port_path: Option<String>,
+    /// Axes which the device must support, for picking the right one of several event devices
+    /// belonging to the same physical device.
+    #[serde(default)]
+    pub has_axes: Vec<Axis>,
+    /// Which of several matching devices to use, counting from 0 in order of their port path.
+    pub index: Option<usize>,
+}
+
+impl RealDeviceMatcher {
+    /// Whether the matcher consists of nothing but a path, in which case the device can be opened
+    /// without consulting udev.
+    pub fn is_path_only(&self) -> bool {
+        return self.path.is_some()
+            && self.index.is_none()
+            && RealDeviceMatcher {
+                path: None,
+                ..self.clone()
+            }
+            .is_empty();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.path.is_none()
+            && self.vendor_id.is_none()
+            && self.product_id.is_none()
+            && self.name.is_none()
+            && self.name_regex.is_none()
+            && self.phys.is_none()
+            && self.uniq.is_none()
+            && self.bustype.is_none()
+            && self.udev_properties.is_empty()
+            && self.by_id.is_none()
+            && self.port_path.is_none()
+            && self.has_axes.is_empty();
+    }
+}
+
+impl Display for RealDeviceMatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(path) = &self.path {
+            criteria.push(format!("path '{}'", path.to_string_lossy()));
+        }
+        if let Some(vendor_id) = self.vendor_id {
+            criteria.push(format!("vendor ID {:04x}", vendor_id));
+        }
+        if let Some(product_id) = self.product_id {
+            criteria.push(format!("product ID {:04x}", product_id));
+        }
+        if let Some(name) = &self.name {
+            criteria.push(format!("name '{}'", name));
+        }
+        if let Some(Pattern(regex)) = &self.name_regex {
+            criteria.push(format!("name matching '{}'", regex));
+        }
+        if let Some(phys) = &self.phys {
+            criteria.push(format!("phys '{}'", phys));
+        }
+        if let Some(uniq) = &self.uniq {
+            criteria.push(format!("uniq '{}'", uniq));
+        }
+        if let Some(bustype) = &self.bustype {
+            criteria.push(format!("bus type {:?}", bustype));
+        }
+        for (key, value) in &self.udev_properties {
+            criteria.push(format!("{}={}", key, value));
+        }
+        if let Some(Pattern(regex)) = &self.by_id {
+            criteria.push(format!("by-id link matching '{}'", regex));
+        }
+        if let Some(port_path) = &self.port_path {
+            criteria.push(format!("port path '{}'", port_path));
+        }
+        if !self.has_axes.is_empty() {
+            criteria.push(format!("axes {:?}", self.has_axes));
+        }
+        if let Some(index) = self.index {
+            criteria.push(format!("index {}", index));
+        }
+        return f.write_str(&criteria.join(", "));
+    }
+}
+
+/// A regular expression given in the config file.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        return Regex::new(&string).map(Pattern).map_err(|err| {
+            D::Error::invalid_value(Unexpected::Str(&string), &err.to_string().as_str())
+        });
+    }
+}
+
+/// An event code given by its kernel name, such as `BTN_TRIGGER` or `ABS_HAT0X`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct EventCodeName(pub EventCode);
+
+impl FromStr for EventCodeName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return event_codes()
+            .find(|code| event_code_name(code) == s)
+            .map(EventCodeName)
+            .ok_or_else(|| format!("Unknown event code: '{}'", s));
+    }
+}
+
+impl<'de> Deserialize<'de> for EventCodeName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        return EventCodeName::from_str(&string)
+            .map_err(|err| D::Error::invalid_value(Unexpected::Str(&string), &err.as_str()));
+    }
+}
+
+/// An input property given by its kernel name, such as `INPUT_PROP_POINTER`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InputPropName(pub InputProp);
+
+impl FromStr for InputPropName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return input_properties()
+            .find(|property| format!("{:?}", property) == s)
+            .map(InputPropName)
+            .ok_or_else(|| format!("Unknown input property: '{}'", s));
+    }
+}
+
+impl<'de> Deserialize<'de> for InputPropName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        return InputPropName::from_str(&string)
+            .map_err(|err| D::Error::invalid_value(Unexpected::Str(&string), &err.as_str()));
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BusType {
+    Pci,
+    Usb,
+    Bluetooth,
+    Virtual,
+    I8042,
+    Rs232,
+    Gameport,
+    Parport,
+    I2c,
+    Host,
+}
+
+impl BusType {
+    /// The numeric bus type as used by the kernel (`BUS_*` in `linux/input.h`).
+    pub fn code(&self) -> u16 {
+        return match self {
+            BusType::Pci => 0x01,
+            BusType::Usb => 0x03,
+            BusType::Bluetooth => 0x05,
+            BusType::Virtual => 0x06,
+            BusType::I8042 => 0x11,
+            BusType::Rs232 => 0x13,
+            BusType::Gameport => 0x14,
+            BusType::Parport => 0x15,
+            BusType::I2c => 0x18,
+            BusType::Host => 0x19,
+        };
+    }
 }
 
+#[derive(Debug, Clone)]
+pub struct RealDeviceConfig {
+    pub matcher: RealDeviceMatcher,
+    pub relative_axes: HashMap<Axis, RelativeAxisConfig>,
+    /// Measured ranges of absolute axes which do not reach the range they claim to have.
+    pub calibration: HashMap<Axis, AxisCalibration>,
+    /// Whether to grab the device exclusively, so that nothing but Pimp-My-Axis receives its events.
+    pub grab: bool,
+}
+
+/// The options are taken out of the map by hand and everything else has to be a criterion of the
+/// matcher. With `#[serde(flatten)]`, a misspelled criterion would be ignored instead of rejected,
+/// which widens the match.
+impl<'de> Deserialize<'de> for RealDeviceConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut map = Mapping::deserialize(deserializer)?;
+        let relative_axes = take_option(&mut map, "relative_axes").map_err(D::Error::custom)?;
+        let calibration = take_option(&mut map, "calibration").map_err(D::Error::custom)?;
+        let grab = take_option(&mut map, "grab").map_err(D::Error::custom)?;
+        let matcher = serde_yaml::from_value(Value::Mapping(map)).map_err(D::Error::custom)?;
+        return Ok(RealDeviceConfig {
+            matcher,
+            relative_axes,
+            calibration,
+            grab,
+        });
+    }
+}
+
+fn take_option<T: DeserializeOwned + Default>(
+    map: &mut Mapping,
+    key: &str,
+) -> Result<T, serde_yaml::Error> {
+    return match map.remove(&Value::String(key.to_owned())) {
+        Some(value) => serde_yaml::from_value(value),
+        None => Ok(T::default()),
+    };
+}
+
+/// A real device may be given either as a bare event device path or as a map containing the
+/// matcher and any further options.
+fn deserialize_real_devices<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, RealDeviceConfig>, D::Error> {
+    let values = HashMap::<String, Value>::deserialize(deserializer)?;
+    let mut result = HashMap::new();
+    for (name, value) in values {
+        let config = match value {
+            Value::String(path) => RealDeviceConfig {
+                matcher: RealDeviceMatcher {
+                    path: Some(PathBuf::from(path)),
+                    ..RealDeviceMatcher::default()
+                },
+                relative_axes: HashMap::new(),
+                calibration: HashMap::new(),
+                grab: false,
+            },
+            value => serde_yaml::from_value(value)
+                .map_err(|err| D::Error::custom(format!("Real device '{}': {}", name, err)))?,
+        };
+        result.insert(name, config);
+    }
+    return Ok(result);
+}
+
+/// Relative axes report deltas, which are summed up into an absolute value.
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RelativeAxisConfig {
+    #[serde(default = "default_relative_min")]
+    pub min: i32,
+    #[serde(default = "default_relative_max")]
+    pub max: i32,
+    #[serde(default)]
+    pub center: i32,
+    /// Speed in units per second at which the accumulated value returns to `center`.
+    #[serde(default)]
+    pub decay: Option<u32>,
+}
+
+impl Default for RelativeAxisConfig {
+    fn default() -> Self {
+        return RelativeAxisConfig {
+            min: default_relative_min(),
+            max: default_relative_max(),
+            center: 0,
+            decay: None,
+        };
+    }
+}
+
+/// The raw values an absolute axis actually reports at its extremes and at rest. They are mapped
+/// onto the full range the device declares for the axis, with `center` ending up in the middle.
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AxisCalibration {
+    pub min: i32,
+    pub center: Option<i32>,
+    pub max: i32,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct VirtDeviceConfig {
     #[serde(default = "default_virt_name")]
     pub name: String,
@@ -40,7 +335,118 @@ pub struct VirtDeviceConfig {
     pub vendor_id: u16,
     #[serde(default = "default_product_id")]
     pub product_id: u16,
+    /// Real device whose events are forwarded unchanged, except for those of the axes defined
+    /// here.
+    #[serde(default, deserialize_with = "deserialize_passthrough")]
+    pub passthrough: Option<PassthroughConfig>,
+    /// Real device whose name, IDs and capabilities the virtual device takes over, such that it
+    /// looks just like it.
+    #[serde(default, deserialize_with = "deserialize_clone")]
+    pub clone: Option<CloneConfig>,
+    /// Overrides the bus type, which is otherwise taken from the cloned device, if any.
+    #[serde(default)]
+    pub bustype: Option<BusType>,
+    /// Overrides the version, which is otherwise taken from the cloned device, if any.
+    #[serde(default)]
+    pub version: Option<u16>,
+    /// Overrides the physical location, which is otherwise taken from the cloned device, if any.
+    #[serde(default)]
+    pub phys: Option<String>,
+    /// Input properties, in addition to those of the cloned device.
+    #[serde(default)]
+    pub properties: Vec<InputPropName>,
+    #[serde(default)]
     pub axes: HashMap<Axis, AxisConfig>,
+    /// Buttons along with expressions which press them while they are not 0.
+    #[serde(default)]
+    pub buttons: HashMap<Axis, AxisExpression>,
+    #[serde(default)]
+    pub hats: HashMap<Hat, HatConfig>,
+    /// Relative axes which move at a speed given by their expression.
+    #[serde(default)]
+    pub mouse: HashMap<Axis, MouseAxisConfig>,
+    /// Keys which are pressed repeatedly at a rate given by their expression.
+    #[serde(default)]
+    pub pulses: HashMap<Axis, PulseConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct PassthroughConfig {
+    pub device: String,
+    /// If not empty, only these event codes are passed through.
+    #[serde(default)]
+    pub include: Vec<EventCodeName>,
+    #[serde(default)]
+    pub exclude: Vec<EventCodeName>,
+    /// Values the passed through event codes rest at when shutting down or while the device is
+    /// disconnected, instead of the center of axes and released buttons.
+    #[serde(default)]
+    pub park: HashMap<EventCodeName, i32>,
+}
+
+impl PassthroughConfig {
+    pub fn includes(&self, event_code: &EventCode) -> bool {
+        let listed = |codes: &[EventCodeName]| codes.iter().any(|code| code.0 == *event_code);
+        return (self.include.is_empty() || listed(&self.include)) && !listed(&self.exclude);
+    }
+
+    /// The configured value an event code rests at, if any.
+    pub fn park_value(&self, event_code: &EventCode) -> Option<i32> {
+        return self.park.get(&EventCodeName(*event_code)).copied();
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct CloneConfig {
+    pub device: String,
+    /// Appended to the name of the real device.
+    #[serde(default)]
+    pub name_suffix: String,
+}
+
+/// Cloning may be given either as just the name of the real device or as a map.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CloneConfigRepr {
+    Device(String),
+    Full(CloneConfig),
+}
+
+fn deserialize_clone<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<CloneConfig>, D::Error> {
+    return Ok(Some(match CloneConfigRepr::deserialize(deserializer)? {
+        CloneConfigRepr::Device(device) => CloneConfig {
+            device,
+            name_suffix: String::new(),
+        },
+        CloneConfigRepr::Full(config) => config,
+    }));
+}
+
+/// Passthrough may be given either as just the name of the real device or as a map also
+/// containing the event codes to include or exclude.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PassthroughConfigRepr {
+    Device(String),
+    Full(PassthroughConfig),
+}
+
+fn deserialize_passthrough<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PassthroughConfig>, D::Error> {
+    return Ok(Some(
+        match PassthroughConfigRepr::deserialize(deserializer)? {
+            PassthroughConfigRepr::Device(device) => PassthroughConfig {
+                device,
+                include: Vec::new(),
+                exclude: Vec::new(),
+                park: HashMap::new(),
+            },
+            PassthroughConfigRepr::Full(config) => config,
+        },
+    ));
 }
 
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -48,6 +454,260 @@ pub struct AxisConfig {
     pub min: i32,
     pub max: i32,
     pub expr: AxisExpression,
+    /// Value to output while a real device the expression depends on is disconnected.
+    #[serde(default)]
+    pub fallback: Option<i32>,
+    /// Changes smaller than this are filtered out by the kernel.
+    #[serde(default)]
+    pub fuzz: i32,
+    /// Values within this distance from the center are treated as 0 by most games.
+    #[serde(default)]
+    pub flat: i32,
+    /// Units per millimeter, or per radian for rotational axes.
+    #[serde(default)]
+    pub resolution: i32,
+    #[serde(default)]
+    pub out_of_range: OutOfRange,
+    /// Value to output when shutting down. Defaults to the center of the range.
+    #[serde(default)]
+    pub park: Option<i32>,
+}
+
+impl AxisConfig {
+    /// Creates the config of an axis without fuzz, flat or resolution, such as the ones making up
+    /// buttons or hats.
+    pub fn new(min: i32, max: i32, expr: AxisExpression, fallback: Option<i32>) -> AxisConfig {
+        return AxisConfig {
+            min,
+            max,
+            expr,
+            fallback,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+            out_of_range: OutOfRange::default(),
+            park: None,
+        };
+    }
+
+    pub fn park_value(&self) -> i32 {
+        return self
+            .park
+            .unwrap_or_else(|| ((self.min as i64 + self.max as i64) / 2) as i32);
+    }
+}
+
+/// What happens to values of an expression outside the range of its virtual axis.
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfRange {
+    /// Outputs the nearest end of the range.
+    Clamp,
+    /// Continues from the other end of the range.
+    Wrap,
+    /// Keeps the previous value.
+    Error,
+    /// Maps the values the expression can produce onto the range.
+    Scale,
+}
+
+impl Default for OutOfRange {
+    fn default() -> Self {
+        return OutOfRange::Clamp;
+    }
+}
+
+impl OutOfRange {
+    /// Brings a value into `min..=max`. For `Scale`, `bounds` are the lowest and highest value the
+    /// expression can produce, if known. Returns None for `Error` if the value is out of range.
+    pub fn apply(&self, value: i32, min: i32, max: i32, bounds: Option<(i64, i64)>) -> Option<i32> {
+        let (value, min, max) = (value as i128, min as i128, max as i128);
+        let result = match self {
+            OutOfRange::Clamp => value.clamp(min, max),
+            OutOfRange::Wrap => min + (value - min).rem_euclid(max - min + 1),
+            OutOfRange::Error if value < min || value > max => return None,
+            OutOfRange::Error => value,
+            OutOfRange::Scale => match bounds {
+                Some((low, high)) if low < high => {
+                    let (low, high) = (low as i128, high as i128);
+                    (min + (value - low) * (max - min) / (high - low)).clamp(min, max)
+                }
+                // Without known bounds there is nothing to scale from.
+                _ => value.clamp(min, max),
+            },
+        };
+        return Some(result as i32);
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MouseAxisConfig {
+    pub expr: AxisExpression,
+    /// Value of the expression at full deflection.
+    pub max: i32,
+    /// Speed in units per second at full deflection.
+    #[serde(default = "default_mouse_sensitivity")]
+    pub sensitivity: f64,
+    /// Exponent applied to the deflection. Values above 1 give finer control close to the center.
+    #[serde(default = "default_mouse_acceleration")]
+    pub acceleration: f64,
+}
+
+impl MouseAxisConfig {
+    /// Speed in units per second for the given value of the expression.
+    pub fn speed(&self, value: i32) -> f64 {
+        let deflection = (value as f64 / self.max as f64).clamp(-1.0, 1.0);
+        return self.sensitivity * deflection.signum() * deflection.abs().powf(self.acceleration);
+    }
+}
+
+fn default_mouse_sensitivity() -> f64 {
+    return 1000.0;
+}
+
+fn default_mouse_acceleration() -> f64 {
+    return 1.0;
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PulseConfig {
+    pub expr: AxisExpression,
+    /// Value of the expression at which the key is pressed at the full `rate`.
+    pub max: i32,
+    /// Presses per second at `max`.
+    pub rate: f64,
+    /// Milliseconds for which each press is held, such that games reading the state of the key
+    /// once per frame see it. Always lasts until the next tick at least.
+    #[serde(default = "default_pulse_hold")]
+    pub hold: u64,
+}
+
+impl PulseConfig {
+    /// Presses per second for the given value of the expression. Values up to 0 do not press the
+    /// key at all.
+    pub fn rate(&self, value: i32) -> f64 {
+        return self.rate * (value as f64 / self.max as f64).clamp(0.0, 1.0);
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Hat {
+    #[serde(rename = "HAT0")]
+    Hat0,
+    #[serde(rename = "HAT1")]
+    Hat1,
+    #[serde(rename = "HAT2")]
+    Hat2,
+    #[serde(rename = "HAT3")]
+    Hat3,
+}
+
+impl Hat {
+    /// The X and Y axes the hat is output on.
+    pub fn axes(&self) -> (Axis, Axis) {
+        return match self {
+            Hat::Hat0 => (Axis::Hat0X, Axis::Hat0Y),
+            Hat::Hat1 => (Axis::Hat1X, Axis::Hat1Y),
+            Hat::Hat2 => (Axis::Hat2X, Axis::Hat2Y),
+            Hat::Hat3 => (Axis::Hat3X, Axis::Hat3Y),
+        };
+    }
+}
+
+/// A hat switch is either driven by one expression per direction, which point it that way while
+/// they are not 0, or by a stick which points it in the direction it is pushed beyond
+/// `threshold`.
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum HatConfig {
+    Buttons {
+        up: AxisExpression,
+        down: AxisExpression,
+        left: AxisExpression,
+        right: AxisExpression,
+    },
+    Stick {
+        x: AxisExpression,
+        y: AxisExpression,
+        threshold: i32,
+        /// Either 4, in which case only the direction the stick is pushed furthest in counts, or 8,
+        /// which also allows diagonals.
+        #[serde(default = "default_hat_directions")]
+        directions: u8,
+    },
+}
+
+impl HatConfig {
+    /// Expressions for the X and Y axes of the hat, each of which results in -1, 0 or 1.
+    pub fn expressions(&self) -> (AxisExpression, AxisExpression) {
+        return match self {
+            HatConfig::Buttons {
+                up,
+                down,
+                left,
+                right,
+            } => {
+                let pressed = |expr: &AxisExpression| {
+                    biop(Operator::NotEqual, expr.clone(), AxisExpression::Literal(0))
+                };
+                (
+                    biop(Operator::Sub, pressed(right), pressed(left)),
+                    biop(Operator::Sub, pressed(down), pressed(up)),
+                )
+            }
+            HatConfig::Stick {
+                x,
+                y,
+                threshold,
+                directions,
+            } => {
+                let direction = |expr: &AxisExpression| {
+                    biop(
+                        Operator::Sub,
+                        biop(
+                            Operator::Greater,
+                            expr.clone(),
+                            AxisExpression::Literal(*threshold),
+                        ),
+                        biop(
+                            Operator::Less,
+                            expr.clone(),
+                            AxisExpression::Literal(-*threshold),
+                        ),
+                    )
+                };
+                if *directions != 4 {
+                    return (direction(x), direction(y));
+                }
+
+                // |x| >= |y| exactly if x lies on the same side of the lines x = y and x = -y.
+                let negated_y = biop(Operator::Sub, AxisExpression::Literal(0), y.clone());
+                let same_side = |op| {
+                    biop(
+                        op,
+                        biop(Operator::GreaterOrEqual, x.clone(), y.clone()),
+                        biop(Operator::GreaterOrEqual, x.clone(), negated_y.clone()),
+                    )
+                };
+                (
+                    biop(Operator::Mul, direction(x), same_side(Operator::Equal)),
+                    biop(Operator::Mul, direction(y), same_side(Operator::NotEqual)),
+                )
+            }
+        };
+    }
+}
+
+fn biop(op: Operator, left: AxisExpression, right: AxisExpression) -> AxisExpression {
+    return AxisExpression::BiOp(op, Box::new(left), Box::new(right));
+}
+
+fn default_pulse_hold() -> u64 {
+    return 50;
+}
+
+fn default_hat_directions() -> u8 {
+    return 8;
 }
 
 fn default_virt_name() -> String {
@@ -62,7 +722,17 @@ fn default_product_id() -> u16 {
     return 0x0001;
 }
 
-#[derive(Deserialize, Eq, PartialEq, Hash, Debug, Clone, Copy)]
+fn default_relative_min() -> i32 {
+    return i32::MIN;
+}
+
+fn default_relative_max() -> i32 {
+    return i32::MAX;
+}
+
+/// An axis or button of a device. Buttons can only be used in expressions and as `buttons` of
+/// virtual devices, where their value is 1 while pressed and 0 otherwise.
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
 pub enum Axis {
     X,
     Y,
@@ -75,6 +745,99 @@ pub enum Axis {
     Wheel,
     Gas,
     Brake,
+    RelX,
+    RelY,
+    RelZ,
+    RelRX,
+    RelRY,
+    RelRZ,
+    RelHWheel,
+    RelDial,
+    RelWheel,
+    RelMisc,
+    Hat0X,
+    Hat0Y,
+    Hat1X,
+    Hat1Y,
+    Hat2X,
+    Hat2Y,
+    Hat3X,
+    Hat3Y,
+    Key(EV_KEY),
+}
+
+impl Axis {
+    pub const ABSOLUTE: [Axis; 11] = [
+        Axis::X,
+        Axis::Y,
+        Axis::Z,
+        Axis::RX,
+        Axis::RY,
+        Axis::RZ,
+        Axis::Throttle,
+        Axis::Rudder,
+        Axis::Wheel,
+        Axis::Gas,
+        Axis::Brake,
+    ];
+
+    pub fn is_key(&self) -> bool {
+        return matches!(self, Axis::Key(_));
+    }
+
+    pub fn is_relative(&self) -> bool {
+        return matches!(
+            self,
+            Axis::RelX
+                | Axis::RelY
+                | Axis::RelZ
+                | Axis::RelRX
+                | Axis::RelRY
+                | Axis::RelRZ
+                | Axis::RelHWheel
+                | Axis::RelDial
+                | Axis::RelWheel
+                | Axis::RelMisc
+        );
+    }
+}
+
+impl Display for Axis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Axis::Key(key) => return write!(f, "{:?}", key),
+            Axis::X => "X",
+            Axis::Y => "Y",
+            Axis::Z => "Z",
+            Axis::RX => "RX",
+            Axis::RY => "RY",
+            Axis::RZ => "RZ",
+            Axis::Throttle => "Throttle",
+            Axis::Rudder => "Rudder",
+            Axis::Wheel => "Wheel",
+            Axis::Gas => "Gas",
+            Axis::Brake => "Brake",
+            Axis::RelX => "REL_X",
+            Axis::RelY => "REL_Y",
+            Axis::RelZ => "REL_Z",
+            Axis::RelRX => "REL_RX",
+            Axis::RelRY => "REL_RY",
+            Axis::RelRZ => "REL_RZ",
+            Axis::RelHWheel => "REL_HWHEEL",
+            Axis::RelDial => "REL_DIAL",
+            Axis::RelWheel => "REL_WHEEL",
+            Axis::RelMisc => "REL_MISC",
+            Axis::Hat0X => "HAT0X",
+            Axis::Hat0Y => "HAT0Y",
+            Axis::Hat1X => "HAT1X",
+            Axis::Hat1Y => "HAT1Y",
+            Axis::Hat2X => "HAT2X",
+            Axis::Hat2Y => "HAT2Y",
+            Axis::Hat3X => "HAT3X",
+            Axis::Hat3Y => "HAT3Y",
+        };
+        return f.write_str(name);
+    }
 }
 
 impl FromStr for Axis {
@@ -93,19 +856,367 @@ impl FromStr for Axis {
             "Wheel" => Ok(Axis::Wheel),
             "Gas" => Ok(Axis::Gas),
             "Brake" => Ok(Axis::Brake),
-            _ => Err(format!("Unknown axis name: '{}'", s)),
+            "REL_X" => Ok(Axis::RelX),
+            "REL_Y" => Ok(Axis::RelY),
+            "REL_Z" => Ok(Axis::RelZ),
+            "REL_RX" => Ok(Axis::RelRX),
+            "REL_RY" => Ok(Axis::RelRY),
+            "REL_RZ" => Ok(Axis::RelRZ),
+            "REL_HWHEEL" => Ok(Axis::RelHWheel),
+            "REL_DIAL" => Ok(Axis::RelDial),
+            "REL_WHEEL" => Ok(Axis::RelWheel),
+            "REL_MISC" => Ok(Axis::RelMisc),
+            "HAT0X" => Ok(Axis::Hat0X),
+            "HAT0Y" => Ok(Axis::Hat0Y),
+            "HAT1X" => Ok(Axis::Hat1X),
+            "HAT1Y" => Ok(Axis::Hat1Y),
+            "HAT2X" => Ok(Axis::Hat2X),
+            "HAT2Y" => Ok(Axis::Hat2Y),
+            "HAT3X" => Ok(Axis::Hat3X),
+            "HAT3Y" => Ok(Axis::Hat3Y),
+            _ => match EventCodeName::from_str(s) {
+                Ok(EventCodeName(EventCode::EV_KEY(key))) => Ok(Axis::Key(key)),
+                _ => Err(format!("Unknown axis name: '{}'", s)),
+            },
         };
     }
 }
 
+impl<'de> Deserialize<'de> for Axis {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        return Axis::from_str(&string)
+            .map_err(|err| D::Error::invalid_value(Unexpected::Str(&string), &err.as_str()));
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub real_devices: HashMap<String, RealDeviceMatcher>,
+    #[serde(deserialize_with = "deserialize_real_devices")]
+    pub real_devices: HashMap<String, RealDeviceConfig>,
     pub virt_devices: HashMap<String, VirtDeviceConfig>,
 }
 
-pub fn parse_config(path: &Path) -> Config {
+pub fn parse_config(path: &Path) -> Result<Config, ConfigError> {
     info!("Reading config file '{}'", path.to_string_lossy());
-    let file = File::open(path).unwrap();
-    return serde_yaml::from_reader(file).unwrap();
+    let file = File::open(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    return serde_yaml::from_reader(file).map_err(|source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    });
+}
+
+/// Sets a value in the config file, leaving the rest of the file including comments untouched.
+/// `keys` is the path to the value, which is inserted along with any missing parent keys.
+pub fn update_config_file(path: &Path, keys: &[&str], value: &str) -> Result<(), ConfigError> {
+    info!(
+        "Setting {} to {} in config file '{}'",
+        keys.join("."),
+        value,
+        path.to_string_lossy()
+    );
+    let io_error = |source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let text = fs::read_to_string(path).map_err(io_error)?;
+    let text = set_yaml_value(&text, keys, value).map_err(ConfigError::Update)?;
+    return fs::write(path, text).map_err(io_error);
+}
+
+/// Sets a value in a YAML document written in block style. Only the lines on the path to the value
+/// are touched.
+pub fn set_yaml_value(text: &str, keys: &[&str], value: &str) -> Result<String, String> {
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    let (mut start, mut end) = (0, lines.len());
+    let mut parent_indent: Option<usize> = None;
+
+    for (depth, key) in keys.iter().enumerate() {
+        let is_last = depth == keys.len() - 1;
+        let block = &lines[start..end];
+        let line = match block.iter().position(|line| {
+            !is_yaml_blank(line) && yaml_key(line) == Some(*key) && {
+                let indent = yaml_indentation(line);
+                Some(indent) == block_indentation(block)
+            }
+        }) {
+            Some(line) => start + line,
+            None => {
+                let indent = block_indentation(block)
+                    .unwrap_or_else(|| parent_indent.map_or(0, |indent| indent + 2));
+                let insert_at = (start..end)
+                    .rev()
+                    .find(|&i| !is_yaml_blank(&lines[i]))
+                    .map_or(start, |i| i + 1);
+                lines.insert(insert_at, format!("{}{}:", " ".repeat(indent), key));
+                end += 1;
+                insert_at
+            }
+        };
+
+        let indent = yaml_indentation(&lines[line]);
+        if is_last {
+            lines[line] = format!("{}{}: {}", " ".repeat(indent), key, value);
+            break;
+        }
+
+        let rest = lines[line].split_once(':').map_or("", |(_, rest)| rest);
+        if !is_yaml_blank(rest) {
+            return Err(format!(
+                "Cannot set {} because {} is not a map",
+                keys.join("."),
+                keys[..=depth].join(".")
+            ));
+        }
+
+        parent_indent = Some(indent);
+        start = line + 1;
+        end = (start..end)
+            .find(|&i| !is_yaml_blank(&lines[i]) && yaml_indentation(&lines[i]) <= indent)
+            .unwrap_or(end);
+    }
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+    return Ok(result);
+}
+
+fn block_indentation(block: &[String]) -> Option<usize> {
+    return block
+        .iter()
+        .find(|line| !is_yaml_blank(line))
+        .map(|line| yaml_indentation(line));
+}
+
+fn yaml_indentation(line: &str) -> usize {
+    return line.len() - line.trim_start().len();
+}
+
+fn yaml_key(line: &str) -> Option<&str> {
+    let (key, _) = line.trim_start().split_once(':')?;
+    return Some(key.trim().trim_matches('"').trim_matches('\''));
+}
+
+fn is_yaml_blank(line: &str) -> bool {
+    let trimmed = line.trim();
+    return trimmed.is_empty() || trimmed.starts_with('#');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use evdev_rs::enums::{EV_ABS, EV_KEY, EventCode, InputProp};
+
+    use crate::config::{Axis, BusType, Config, Hat, OutOfRange, set_yaml_value, VirtDeviceConfig};
+
+    const CONFIG: &str = "\
+real_devices:
+  stick: /dev/input/event3
+virt_devices:
+  # Comment
+  my_device:
+    axes:
+      X:
+        min: -255 # Comment
+        max: 255
+        expr: \"stick:X\"
+      Y:
+        expr: \"stick:Y\"
+
+";
+
+    #[test]
+    fn set_yaml_value_replaces_and_inserts() {
+        let updated = set_yaml_value(
+            CONFIG,
+            &["virt_devices", "my_device", "axes", "X", "expr"],
+            "\"stick:X * 2\"",
+        )
+        .unwrap();
+        assert_eq!(updated, CONFIG.replace("\"stick:X\"", "\"stick:X * 2\""));
+
+        let updated = set_yaml_value(
+            CONFIG,
+            &["virt_devices", "my_device", "axes", "X", "fuzz"],
+            "4",
+        )
+        .unwrap();
+        assert!(updated.contains("        expr: \"stick:X\"\n        fuzz: 4\n      Y:"));
+
+        let updated = set_yaml_value(CONFIG, &["virt_devices", "other", "name"], "Other").unwrap();
+        assert!(updated.ends_with("        expr: \"stick:Y\"\n  other:\n    name: Other\n\n"));
+
+        assert!(set_yaml_value(CONFIG, &["real_devices", "stick", "grab"], "true").is_err());
+    }
+
+    #[test]
+    fn real_device_rejects_unknown_keys() {
+        let config: Config =
+            serde_yaml::from_str("real_devices: {stick: /dev/input/event3}\nvirt_devices: {}")
+                .unwrap();
+        assert_eq!(
+            config.real_devices["stick"].matcher.path,
+            Some("/dev/input/event3".into())
+        );
+
+        let config: Config = serde_yaml::from_str(
+            "real_devices: {stick: {vendor_id: 0x044f, grab: true}}\nvirt_devices: {}",
+        )
+        .unwrap();
+        assert_eq!(config.real_devices["stick"].matcher.vendor_id, Some(0x044f));
+        assert!(config.real_devices["stick"].grab);
+
+        let err = serde_yaml::from_str::<Config>(
+            "real_devices: {stick: {vendorid: 0x044f, grab: true}}\nvirt_devices: {}",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown field `vendorid`"));
+    }
+
+    #[test]
+    fn path_with_index_is_not_path_only() {
+        let config: Config = serde_yaml::from_str(
+            "
+real_devices:
+  stick: /dev/input/event3
+  throttle: {path: /dev/input/event4, index: 1}
+virt_devices: {}
+",
+        )
+        .unwrap();
+        assert!(config.real_devices["stick"].matcher.is_path_only());
+        assert!(!config.real_devices["throttle"].matcher.is_path_only());
+    }
+
+    #[test]
+    fn passthrough_filters_event_codes() {
+        let config: VirtDeviceConfig = serde_yaml::from_str("passthrough: stick").unwrap();
+        let passthrough = config.passthrough.unwrap();
+        assert_eq!(passthrough.device, "stick");
+        assert!(passthrough.includes(&EventCode::EV_KEY(EV_KEY::BTN_TRIGGER)));
+
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "passthrough: { device: stick, include: [BTN_TRIGGER, ABS_HAT0X], exclude: [ABS_HAT0X] }",
+        )
+        .unwrap();
+        let passthrough = config.passthrough.unwrap();
+        assert!(passthrough.includes(&EventCode::EV_KEY(EV_KEY::BTN_TRIGGER)));
+        assert!(!passthrough.includes(&EventCode::EV_ABS(EV_ABS::ABS_HAT0X)));
+        assert!(!passthrough.includes(&EventCode::EV_KEY(EV_KEY::BTN_THUMB)));
+
+        assert!(serde_yaml::from_str::<VirtDeviceConfig>(
+            "passthrough: { device: stick, include: [BTN_NONSENSE] }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn passthrough_park_values() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "passthrough: { device: pedals, park: { ABS_THROTTLE: 0, BTN_TRIGGER: 1 } }",
+        )
+        .unwrap();
+        let passthrough = config.passthrough.unwrap();
+        assert_eq!(
+            passthrough.park_value(&EventCode::EV_ABS(EV_ABS::ABS_THROTTLE)),
+            Some(0)
+        );
+        assert_eq!(
+            passthrough.park_value(&EventCode::EV_KEY(EV_KEY::BTN_TRIGGER)),
+            Some(1)
+        );
+        assert_eq!(
+            passthrough.park_value(&EventCode::EV_ABS(EV_ABS::ABS_X)),
+            None
+        );
+    }
+
+    #[test]
+    fn hats_point_in_direction() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "\
+hats:
+  HAT0: { up: \"stick:BTN_THUMB\", down: \"0\", left: \"stick:X < 0\", right: \"stick:X > 0\" }
+  HAT1: { x: \"stick:RX\", y: \"stick:RY\", threshold: 100 }
+  HAT2: { x: \"stick:RX\", y: \"stick:RY\", threshold: 100, directions: 4 }
+",
+        )
+        .unwrap();
+        let direction = |hat: Hat, rx: i32, ry: i32| {
+            let mut values = HashMap::new();
+            values.insert(("stick".to_owned(), Axis::X), -5);
+            values.insert(("stick".to_owned(), Axis::Key(EV_KEY::BTN_THUMB)), 1);
+            values.insert(("stick".to_owned(), Axis::RX), rx);
+            values.insert(("stick".to_owned(), Axis::RY), ry);
+            let (x, y) = config.hats[&hat].expressions();
+            return (x.eval(&values).unwrap(), y.eval(&values).unwrap());
+        };
+
+        assert_eq!(direction(Hat::Hat0, 0, 0), (-1, -1));
+        assert_eq!(direction(Hat::Hat1, 50, -200), (0, -1));
+        assert_eq!(direction(Hat::Hat1, 150, -200), (1, -1));
+        assert_eq!(direction(Hat::Hat2, 150, -200), (0, -1));
+        assert_eq!(direction(Hat::Hat2, -250, 200), (-1, 0));
+        assert_eq!(direction(Hat::Hat2, 50, 50), (0, 0));
+    }
+
+    #[test]
+    fn mouse_and_pulses_follow_deflection() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "mouse: { REL_X: { expr: \"stick:RX\", max: 1000, sensitivity: 500, acceleration: 2 } }",
+        )
+        .unwrap();
+        let mouse_config = &config.mouse[&Axis::RelX];
+        assert_eq!(mouse_config.speed(0), 0.0);
+        assert_eq!(mouse_config.speed(-500), -125.0);
+        assert_eq!(mouse_config.speed(3000), 500.0);
+
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "pulses: { KEY_EQUAL: { expr: \"stick:RY\", max: 1000, rate: 10 } }",
+        )
+        .unwrap();
+        let pulse_config = &config.pulses[&Axis::Key(EV_KEY::KEY_EQUAL)];
+        assert_eq!(pulse_config.rate(-500), 0.0);
+        assert_eq!(pulse_config.rate(250), 2.5);
+    }
+
+    #[test]
+    fn identity_overrides() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "{ bustype: usb, version: 0x0111, phys: usb-0000:00:14.0-2/input0, properties: [INPUT_PROP_POINTER] }",
+        )
+        .unwrap();
+        assert_eq!(config.bustype, Some(BusType::Usb));
+        assert_eq!(config.version, Some(0x0111));
+        assert_eq!(config.phys.as_deref(), Some("usb-0000:00:14.0-2/input0"));
+        assert_eq!(config.properties[0].0, InputProp::INPUT_PROP_POINTER);
+
+        assert!(serde_yaml::from_str::<VirtDeviceConfig>("properties: [INPUT_PROP_MAX]").is_err());
+    }
+
+    #[test]
+    fn out_of_range_policies() {
+        assert_eq!(OutOfRange::Clamp.apply(300, -255, 255, None), Some(255));
+        assert_eq!(OutOfRange::Wrap.apply(360, 0, 359, None), Some(0));
+        assert_eq!(OutOfRange::Wrap.apply(-1, 0, 359, None), Some(359));
+        assert_eq!(OutOfRange::Error.apply(-256, -255, 255, None), None);
+        assert_eq!(OutOfRange::Error.apply(100, -255, 255, None), Some(100));
+        assert_eq!(
+            OutOfRange::Scale.apply(0, -255, 255, Some((0, 1020))),
+            Some(-255)
+        );
+        assert_eq!(
+            OutOfRange::Scale.apply(510, -255, 255, Some((0, 1020))),
+            Some(0)
+        );
+        assert_eq!(
+            OutOfRange::Scale.apply(1020, -255, 255, Some((0, 1020))),
+            Some(255)
+        );
+        assert_eq!(OutOfRange::Scale.apply(1000, -255, 255, None), Some(255));
+    }
 }
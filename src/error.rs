@@ -0,0 +1,313 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::Axis;
+use crate::expr::Operator;
+
+/// Any error which ends the program, grouped by what the user would have to look at to fix it.
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    Device(DeviceError),
+    Expression(ExpressionError),
+    Runtime(RuntimeError),
+}
+
+impl Error {
+    /// The exit code of the program for this error, following `sysexits.h`.
+    pub fn exit_code(&self) -> i32 {
+        return match self {
+            Error::Config(_) => 78,
+            Error::Device(_) => 69,
+            Error::Expression(_) => 65,
+            Error::Runtime(_) => 70,
+        };
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Error::Config(err) => write!(f, "Invalid configuration: {}", err),
+            Error::Device(err) => write!(f, "Device error: {}", err),
+            Error::Expression(err) => write!(f, "Expression error: {}", err),
+            Error::Runtime(err) => write!(f, "{}", err),
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Config(err) => Some(err),
+            Error::Device(err) => Some(err),
+            Error::Expression(err) => Some(err),
+            Error::Runtime(err) => Some(err),
+        };
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        return Error::Config(err);
+    }
+}
+
+impl From<DeviceError> for Error {
+    fn from(err: DeviceError) -> Self {
+        return Error::Device(err);
+    }
+}
+
+impl From<ExpressionError> for Error {
+    fn from(err: ExpressionError) -> Self {
+        return Error::Expression(err);
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        return Error::Runtime(err);
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// No config file was given and there is none in the default locations.
+    NotFound,
+    Directories(xdg::BaseDirectoriesError),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// The config is well-formed, but contradicts itself, e.g. by referencing an undefined device.
+    Invalid {
+        context: String,
+        message: String,
+    },
+    /// A value could not be written back to the config file.
+    Update(String),
+}
+
+impl ConfigError {
+    pub fn invalid(context: impl Into<String>, message: impl Into<String>) -> ConfigError {
+        return ConfigError::Invalid {
+            context: context.into(),
+            message: message.into(),
+        };
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            ConfigError::NotFound => write!(f, "Found no config file"),
+            ConfigError::Directories(source) => {
+                write!(f, "Unable to find the config directories: {}", source)
+            }
+            ConfigError::Io { path, source } => {
+                write!(
+                    f,
+                    "Unable to access '{}': {}",
+                    path.to_string_lossy(),
+                    source
+                )
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "{}: {}", path.to_string_lossy(), source)
+            }
+            ConfigError::Invalid { context, message } => write!(f, "{}: {}", context, message),
+            ConfigError::Update(message) => write!(f, "{}", message),
+        };
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            ConfigError::Directories(source) => Some(source),
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug)]
+pub enum DeviceError {
+    /// No event device, or more than one, matches the config of a real device.
+    NotFound {
+        device: String,
+        message: String,
+    },
+    Open {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Grab {
+        path: PathBuf,
+        source: io::Error,
+    },
+    NotConnected {
+        device: String,
+    },
+    Unsupported {
+        device: String,
+        axis: Axis,
+    },
+    /// Creating or writing to a uinput device failed.
+    Uinput {
+        device: String,
+        source: io::Error,
+    },
+    Udev(libudev::Error),
+}
+
+impl Display for DeviceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            DeviceError::NotFound { device, message } => write!(f, "{}: {}", device, message),
+            DeviceError::Open { path, source } => {
+                write!(f, "Unable to open '{}': {}", path.to_string_lossy(), source)
+            }
+            DeviceError::Grab { path, source } => {
+                write!(f, "Unable to grab '{}': {}", path.to_string_lossy(), source)
+            }
+            DeviceError::NotConnected { device } => write!(f, "Device {} is not connected", device),
+            DeviceError::Unsupported { device, axis } => {
+                write!(f, "Device {} does not support {}", device, axis)
+            }
+            DeviceError::Uinput { device, source } => {
+                write!(f, "Virtual device {}: {}", device, source)
+            }
+            DeviceError::Udev(source) => write!(f, "Unable to query udev: {}", source),
+        };
+    }
+}
+
+impl std::error::Error for DeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            DeviceError::Open { source, .. } => Some(source),
+            DeviceError::Grab { source, .. } => Some(source),
+            DeviceError::Uinput { source, .. } => Some(source),
+            DeviceError::Udev(source) => Some(source),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ExpressionError {
+    Parse(String),
+    UnknownValue {
+        device: String,
+        axis: Axis,
+    },
+    DivisionByZero,
+    Overflow {
+        left: i32,
+        op: Operator,
+        right: i32,
+    },
+    /// An expression was replaced by one referencing different axes.
+    DependenciesChanged {
+        old: String,
+        new: String,
+    },
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            ExpressionError::Parse(message) => write!(f, "{}", message),
+            ExpressionError::UnknownValue { device, axis } => {
+                write!(f, "No value is known for axis {}:{}", device, axis)
+            }
+            ExpressionError::DivisionByZero => write!(f, "Division by zero"),
+            ExpressionError::Overflow { left, op, right } => {
+                write!(f, "Overflow in {} {} {}", left, op, right)
+            }
+            ExpressionError::DependenciesChanged { old, new } => write!(
+                f,
+                "Expression '{}' does not reference the same axes as '{}'",
+                new, old
+            ),
+        };
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// Errors of the machinery around the devices, which are not caused by the config.
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// A system call of the event loop, its timer or its signal handling failed.
+    System {
+        context: String,
+        source: nix::Error,
+    },
+    Udev(libudev::Error),
+    Terminal(String),
+    Io(io::Error),
+}
+
+impl RuntimeError {
+    pub fn system(context: impl Into<String>, source: nix::Error) -> RuntimeError {
+        return RuntimeError::System {
+            context: context.into(),
+            source,
+        };
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            RuntimeError::System { context, source } => write!(f, "{}: {}", context, source),
+            RuntimeError::Udev(source) => write!(f, "Unable to monitor udev: {}", source),
+            RuntimeError::Terminal(message) => {
+                write!(f, "Unable to set up the terminal: {}", message)
+            }
+            RuntimeError::Io(source) => write!(f, "{}", source),
+        };
+    }
+}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            RuntimeError::System { source, .. } => Some(source),
+            RuntimeError::Udev(source) => Some(source),
+            RuntimeError::Io(source) => Some(source),
+            RuntimeError::Terminal(_) => None,
+        };
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        return RuntimeError::Io(err);
+    }
+}
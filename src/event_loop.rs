@@ -0,0 +1,89 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::unistd::close;
+
+use crate::error::RuntimeError;
+
+const MAX_EVENTS: usize = 32;
+
+/// Waits for any of a set of file descriptors to become readable, each of which belongs to a
+/// source of type `T`.
+pub struct EventLoop<T> {
+    epoll_fd: RawFd,
+    sources: HashMap<RawFd, T>,
+}
+
+impl<T: Clone> EventLoop<T> {
+    pub fn new() -> Result<EventLoop<T>, RuntimeError> {
+        let epoll_fd = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)
+            .map_err(|err| RuntimeError::system("Unable to create epoll instance", err))?;
+        return Ok(EventLoop {
+            epoll_fd,
+            sources: HashMap::new(),
+        });
+    }
+
+    pub fn add(&mut self, fd: RawFd, source: T) -> Result<(), RuntimeError> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+        epoll_ctl(self.epoll_fd, EpollOp::EpollCtlAdd, fd, &mut event).map_err(|err| {
+            RuntimeError::system(format!("Unable to watch file descriptor {}", fd), err)
+        })?;
+        self.sources.insert(fd, source);
+        return Ok(());
+    }
+
+    /// Stops watching a file descriptor. This must happen before it is closed, since another one
+    /// may be opened with the same number.
+    pub fn remove(&mut self, fd: RawFd) {
+        if self.sources.remove(&fd).is_some() {
+            // Fails if the file descriptor was closed already, which removes it as well.
+            let _ = epoll_ctl(self.epoll_fd, EpollOp::EpollCtlDel, fd, None);
+        }
+    }
+
+    /// Waits until at least one source is readable or the timeout passes, and returns the
+    /// readable ones. Errors and hangups are reported as readable, such that the next read
+    /// fails.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<Vec<T>, RuntimeError> {
+        let timeout_ms = timeout.map_or(-1, |timeout| timeout.as_millis() as isize);
+        let mut events = [EpollEvent::empty(); MAX_EVENTS];
+        let count = match epoll_wait(self.epoll_fd, &mut events, timeout_ms) {
+            Ok(count) => count,
+            Err(Errno::EINTR) => 0,
+            Err(err) => return Err(RuntimeError::system("Unable to wait for events", err)),
+        };
+        return Ok(events[..count]
+            .iter()
+            .filter_map(|event| self.sources.get(&(event.data() as RawFd)).cloned())
+            .collect());
+    }
+}
+
+impl<T> Drop for EventLoop<T> {
+    fn drop(&mut self) {
+        let _ = close(self.epoll_fd);
+    }
+}
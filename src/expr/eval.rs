@@ -18,23 +18,44 @@
 use std::collections::HashMap;
 
 use crate::config::Axis;
+use crate::error::ExpressionError;
 use crate::expr::AxisExpression;
 use crate::expr::Operator;
 
 impl AxisExpression {
-    pub fn eval(&self, values: &HashMap<(String, Axis), i32>) -> Result<i32, String> {
+    pub fn eval(&self, values: &HashMap<(String, Axis), i32>) -> Result<i32, ExpressionError> {
         return match self {
             AxisExpression::AxisReference(dev, axis) => match values.get(&(dev.clone(), *axis)) {
                 Some(value) => Ok(*value),
-                None => Err(format!("No value is known for axis {}:{:?}", dev, axis)),
+                None => Err(ExpressionError::UnknownValue {
+                    device: dev.clone(),
+                    axis: *axis,
+                }),
             },
             AxisExpression::Literal(value) => Ok(*value),
-            AxisExpression::BiOp(op, left, right) => match op {
-                Operator::Add => Ok(left.eval(values)? + right.eval(values)?),
-                Operator::Sub => Ok(left.eval(values)? - right.eval(values)?),
-                Operator::Mul => Ok(left.eval(values)? * right.eval(values)?),
-                Operator::Div => Ok(left.eval(values)? / right.eval(values)?),
-            },
+            AxisExpression::BiOp(op, left, right) => {
+                let (left, right) = (left.eval(values)?, right.eval(values)?);
+                let result = match op {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Sub => left.checked_sub(right),
+                    Operator::Mul => left.checked_mul(right),
+                    Operator::Div if right == 0 => return Err(ExpressionError::DivisionByZero),
+                    Operator::Div => left.checked_div(right),
+                    Operator::Less => Some((left < right) as i32),
+                    Operator::LessOrEqual => Some((left <= right) as i32),
+                    Operator::Greater => Some((left > right) as i32),
+                    Operator::GreaterOrEqual => Some((left >= right) as i32),
+                    Operator::Equal => Some((left == right) as i32),
+                    Operator::NotEqual => Some((left != right) as i32),
+                    Operator::And => Some((left != 0 && right != 0) as i32),
+                    Operator::Or => Some((left != 0 || right != 0) as i32),
+                };
+                result.ok_or_else(|| ExpressionError::Overflow {
+                    left,
+                    op: op.clone(),
+                    right,
+                })
+            }
         };
     }
 
@@ -50,4 +71,60 @@ impl AxisExpression {
             AxisExpression::Literal(_) => Vec::new(),
         };
     }
+
+    /// The lowest and highest value the expression can produce while each axis it references stays
+    /// within the given range. None if the range of a referenced axis is unknown, or if the
+    /// expression may divide by zero.
+    pub fn bounds(&self, ranges: &HashMap<(String, Axis), (i32, i32)>) -> Option<(i64, i64)> {
+        return match self {
+            AxisExpression::AxisReference(dev, axis) => {
+                let (min, max) = ranges.get(&(dev.clone(), *axis))?;
+                Some((*min as i64, *max as i64))
+            }
+            AxisExpression::Literal(value) => Some((*value as i64, *value as i64)),
+            AxisExpression::BiOp(op, left, right) => {
+                let ((left_min, left_max), (right_min, right_max)) =
+                    (left.bounds(ranges)?, right.bounds(ranges)?);
+                // The extremes of these operations are reached at the ends of both ranges.
+                let corners = |f: fn(i64, i64) -> Option<i64>| -> Option<(i64, i64)> {
+                    let values = [
+                        f(left_min, right_min)?,
+                        f(left_min, right_max)?,
+                        f(left_max, right_min)?,
+                        f(left_max, right_max)?,
+                    ];
+                    return Some((*values.iter().min()?, *values.iter().max()?));
+                };
+                match op {
+                    Operator::Add => Some((
+                        left_min.checked_add(right_min)?,
+                        left_max.checked_add(right_max)?,
+                    )),
+                    Operator::Sub => Some((
+                        left_min.checked_sub(right_max)?,
+                        left_max.checked_sub(right_min)?,
+                    )),
+                    Operator::Mul => corners(i64::checked_mul),
+                    Operator::Div if right_min <= 0 && right_max >= 0 => None,
+                    Operator::Div => corners(i64::checked_div),
+                    _ => Some((0, 1)),
+                }
+            }
+        };
+    }
+}
+
+impl AxisExpression {
+    /// All numeric literals in the expression, from left to right.
+    pub fn literals_mut(&mut self) -> Vec<&mut i32> {
+        return match self {
+            AxisExpression::Literal(value) => vec![value],
+            AxisExpression::BiOp(_, left, right) => {
+                let mut left_literals = left.literals_mut();
+                left_literals.append(&mut right.literals_mut());
+                left_literals
+            }
+            AxisExpression::AxisReference(_, _) => Vec::new(),
+        };
+    }
 }
@@ -15,6 +15,7 @@
  * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer};
@@ -37,8 +38,38 @@ pub enum AxisExpression {
 impl<'de> Deserialize<'de> for AxisExpression {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let string = String::deserialize(deserializer)?;
-        return parse_expr(&string)
-            .map_err(|err| D::Error::invalid_value(Unexpected::Str(&string), &err.as_str()));
+        return parse_expr(&string).map_err(|err| {
+            D::Error::invalid_value(Unexpected::Str(&string), &err.to_string().as_str())
+        });
+    }
+}
+
+/// Formats the expression such that `parse_expr` yields the same expression again.
+impl Display for AxisExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            AxisExpression::AxisReference(dev, axis) => write!(f, "{}:{}", dev, axis),
+            AxisExpression::Literal(value) => write!(f, "{}", value),
+            AxisExpression::BiOp(op, left, right) => {
+                match left.as_ref() {
+                    AxisExpression::BiOp(left_op, _, _)
+                        if left_op.precedence() < op.precedence() =>
+                    {
+                        write!(f, "({})", left)?
+                    }
+                    _ => write!(f, "{}", left)?,
+                }
+                write!(f, " {} ", op)?;
+                match right.as_ref() {
+                    AxisExpression::BiOp(right_op, _, _)
+                        if right_op.precedence() <= op.precedence() =>
+                    {
+                        write!(f, "({})", right)
+                    }
+                    _ => write!(f, "{}", right),
+                }
+            }
+        };
     }
 }
 
@@ -48,6 +79,14 @@ pub enum Operator {
     Sub,
     Mul,
     Div,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
 }
 
 impl FromStr for Operator {
@@ -59,7 +98,51 @@ impl FromStr for Operator {
             "-" => Ok(Operator::Sub),
             "*" => Ok(Operator::Mul),
             "/" => Ok(Operator::Div),
+            "<" => Ok(Operator::Less),
+            "<=" => Ok(Operator::LessOrEqual),
+            ">" => Ok(Operator::Greater),
+            ">=" => Ok(Operator::GreaterOrEqual),
+            "==" => Ok(Operator::Equal),
+            "!=" => Ok(Operator::NotEqual),
+            "&&" => Ok(Operator::And),
+            "||" => Ok(Operator::Or),
             _ => Err(format!("Unknown operator: '{}'", s)),
         };
     }
 }
+
+impl Operator {
+    fn precedence(&self) -> u8 {
+        return match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Less
+            | Operator::LessOrEqual
+            | Operator::Greater
+            | Operator::GreaterOrEqual
+            | Operator::Equal
+            | Operator::NotEqual => 3,
+            Operator::Add | Operator::Sub => 4,
+            Operator::Mul | Operator::Div => 5,
+        };
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+        });
+    }
+}
@@ -23,13 +23,14 @@ use pest::prec_climber::{Assoc, Operator as PestOperator, PrecClimber};
 use pest_derive::Parser;
 
 use crate::config::Axis;
+use crate::error::ExpressionError;
 use crate::expr::{AxisExpression, Operator};
 
 #[derive(Parser)]
 #[grammar = "expr/grammar.pest"]
 struct ExprParser;
 
-type ExprResult = Result<AxisExpression, String>;
+type ExprResult = Result<AxisExpression, ExpressionError>;
 
 fn primary(pair: Pair<'_, Rule>, climber: &PrecClimber<Rule>) -> ExprResult {
     match pair.as_rule() {
@@ -37,14 +38,23 @@ fn primary(pair: Pair<'_, Rule>, climber: &PrecClimber<Rule>) -> ExprResult {
         Rule::axis_ref => match pair.as_str().split_once(':') {
             Some((dev, axis)) => Ok(AxisExpression::AxisReference(
                 dev.to_owned(),
-                Axis::from_str(axis)?,
+                Axis::from_str(axis.trim()).map_err(ExpressionError::Parse)?,
             )),
-            None => Err(format!("Invalid axis reference: {}", pair.as_str())),
+            None => Err(ExpressionError::Parse(format!(
+                "Invalid axis reference: {}",
+                pair.as_str()
+            ))),
         },
-        Rule::literal => Ok(AxisExpression::Literal(
-            pair.as_str().trim().parse().unwrap(),
-        )),
-        _ => panic!(),
+        Rule::literal => match pair.as_str().trim().parse() {
+            Ok(value) => Ok(AxisExpression::Literal(value)),
+            Err(err) => Err(ExpressionError::Parse(format!(
+                "Invalid number {}: {}",
+                pair.as_str().trim(),
+                err
+            ))),
+        },
+        // The grammar does not produce any other rules here.
+        rule => unreachable!("Unexpected rule {:?}", rule),
     }
 }
 
@@ -58,18 +68,27 @@ fn infix(lhs: ExprResult, op: Pair<Rule>, rhs: ExprResult) -> ExprResult {
 
 pub fn parse_expr(input: &str) -> ExprResult {
     let climber = PrecClimber::new(vec![
+        PestOperator::new(Rule::or_op, Assoc::Left),
+        PestOperator::new(Rule::and_op, Assoc::Left),
+        PestOperator::new(Rule::cmp_op, Assoc::Left),
         PestOperator::new(Rule::add_op, Assoc::Left),
         PestOperator::new(Rule::mul_op, Assoc::Left),
     ]);
 
-    let pairs: Pairs<'_, Rule> =
-        ExprParser::parse(Rule::main, input).map_err(|err| err.to_string().to_owned())?;
+    let pairs: Pairs<'_, Rule> = ExprParser::parse(Rule::main, input)
+        .map_err(|err| ExpressionError::Parse(err.to_string()))?;
 
     return climber.climb(pairs, |pair| primary(pair, &climber), infix);
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use evdev_rs::enums::EV_KEY;
+
+    use crate::config::Axis;
+    use crate::expr::{AxisExpression, Operator};
     use crate::expr::parser::parse_expr;
 
     #[test]
@@ -77,4 +96,63 @@ mod tests {
         let parsed = parse_expr("2 + 1 * (1 + 2)").unwrap();
         println!("{:?}", parsed)
     }
+
+    #[test]
+    fn display_round_trip() {
+        for input in &[
+            "2 + 1 * (1 + 2)",
+            "(stick:X - 512) * 2 / 3",
+            "stick:X - (pedals:REL_X - 5)",
+            "stick:Z > 900 || stick:BTN_TRIGGER && stick:X * 2 <= 5",
+            "(stick:BTN_THUMB || stick:BTN_TRIGGER) && stick:Z != 0",
+            "stick:X * -5 - -2147483648",
+        ] {
+            let parsed = parse_expr(input).unwrap();
+            assert_eq!(parsed.to_string(), *input);
+            assert_eq!(parse_expr(&parsed.to_string()).unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn negative_literals() {
+        assert_eq!(parse_expr("-5").unwrap(), AxisExpression::Literal(-5));
+        assert_eq!(
+            parse_expr("3-5").unwrap(),
+            AxisExpression::BiOp(
+                Operator::Sub,
+                Box::new(AxisExpression::Literal(3)),
+                Box::new(AxisExpression::Literal(5))
+            )
+        );
+        assert_eq!(AxisExpression::Literal(i32::MIN).to_string(), "-2147483648");
+        assert!(parse_expr("- 5").is_err());
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        let mut values = HashMap::new();
+        values.insert(("stick".to_owned(), Axis::Z), 950);
+        values.insert(("stick".to_owned(), Axis::Key(EV_KEY::BTN_TRIGGER)), 0);
+
+        let eval = |input: &str| parse_expr(input).unwrap().eval(&values).unwrap();
+        assert_eq!(eval("stick:Z > 900"), 1);
+        assert_eq!(eval("stick:Z > 900 && stick:BTN_TRIGGER"), 0);
+        assert_eq!(eval("stick:BTN_TRIGGER == 0 || stick:Z < 0"), 1);
+        assert_eq!(eval("(stick:Z >= 950) * 10 + 1"), 11);
+    }
+
+    #[test]
+    fn bounds_cover_all_values() {
+        let mut ranges = HashMap::new();
+        ranges.insert(("stick".to_owned(), Axis::X), (-100, 100));
+        ranges.insert(("pedals".to_owned(), Axis::Z), (0, 255));
+
+        let bounds = |input: &str| parse_expr(input).unwrap().bounds(&ranges);
+        assert_eq!(bounds("stick:X * 2 + 5"), Some((-195, 205)));
+        assert_eq!(bounds("stick:X - pedals:Z"), Some((-355, 100)));
+        assert_eq!(bounds("pedals:Z * stick:X / -2"), Some((-12750, 12750)));
+        assert_eq!(bounds("stick:X > 0 || pedals:Z"), Some((0, 1)));
+        assert_eq!(bounds("100 / stick:X"), None);
+        assert_eq!(bounds("stick:Y"), None);
+    }
 }
@@ -0,0 +1,66 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use log::debug;
+
+use crate::error::RuntimeError;
+use crate::real::is_event_device;
+
+/// Watches udev for new event devices.
+pub struct Hotplug {
+    socket: libudev::MonitorSocket,
+}
+
+impl Hotplug {
+    pub fn new() -> Result<Hotplug, RuntimeError> {
+        let context = libudev::Context::new().map_err(RuntimeError::Udev)?;
+        let mut monitor = libudev::Monitor::new(&context).map_err(RuntimeError::Udev)?;
+        monitor
+            .match_subsystem("input")
+            .map_err(RuntimeError::Udev)?;
+        let socket = monitor.listen().map_err(RuntimeError::Udev)?;
+        return Ok(Hotplug { socket });
+    }
+
+    /// Receives all pending udev events and returns whether an event device was added.
+    pub fn device_added(&mut self) -> bool {
+        let mut added = false;
+        while let Some(event) = self.socket.receive_event() {
+            if event.event_type() != libudev::EventType::Add || !is_event_device(event.device()) {
+                continue;
+            }
+
+            debug!(
+                "Event device '{}' was added.",
+                event
+                    .devnode()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            );
+            added = true;
+        }
+        return added;
+    }
+}
+
+impl AsRawFd for Hotplug {
+    fn as_raw_fd(&self) -> RawFd {
+        return self.socket.as_raw_fd();
+    }
+}
@@ -0,0 +1,266 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::Write;
+
+use evdev_rs::DeviceWrapper;
+
+use pimp_my_axis::EventDeviceInfo;
+use pimp_my_axis::cli::{axis_to_event_code, get_event_devices, open_event_device};
+use pimp_my_axis::config::{Axis, RealDeviceMatcher};
+use pimp_my_axis::error::{ConfigError, DeviceError, Error, RuntimeError};
+
+use crate::list_devices::{config_name, matcher_yaml, suggest_matcher};
+
+/// A real device picked for the starter config, along with the ranges of its chosen axes.
+struct SelectedDevice {
+    name: String,
+    evdev_name: String,
+    matcher: RealDeviceMatcher,
+    axes: Vec<(Axis, i32, i32)>,
+}
+
+/// Lets the user pick among the connected joysticks and their axes and writes a config which
+/// passes them through unchanged to the user's config directory.
+pub fn init(force: bool) -> Result<(), Error> {
+    let xdg =
+        xdg::BaseDirectories::with_prefix("pimp-my-axis").map_err(ConfigError::Directories)?;
+    let config_path = xdg
+        .place_config_file("config.yml")
+        .map_err(|source| ConfigError::Io {
+            path: xdg.get_config_home(),
+            source,
+        })?;
+    if config_path.exists() && !force {
+        println!(
+            "{} already exists. Pass --force to overwrite it.",
+            config_path.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    let mut devices = get_event_devices()?;
+    devices.sort_by_key(|device| (device.path.as_os_str().len(), device.path.clone()));
+    let joysticks: Vec<&EventDeviceInfo> = devices
+        .iter()
+        .filter(|device| {
+            device
+                .properties
+                .get("ID_INPUT_JOYSTICK")
+                .map(String::as_str)
+                == Some("1")
+        })
+        .collect();
+    if joysticks.is_empty() {
+        return Err(no_joysticks());
+    }
+
+    println!("Found the following joysticks:");
+    for (index, device) in joysticks.iter().enumerate() {
+        println!(
+            "  {}: {} ({})",
+            index + 1,
+            device.name.as_deref().unwrap_or("Unnamed device"),
+            device.path.to_string_lossy()
+        );
+    }
+    let chosen = prompt_selection("Devices to include", joysticks.len())?;
+
+    let mut used_names = HashSet::new();
+    let mut selected = Vec::new();
+    for device in chosen.into_iter().map(|index| joysticks[index]) {
+        let evdev_device = match open_event_device(&device.path) {
+            Ok(evdev_device) => evdev_device,
+            Err(err) => {
+                println!("Skipping {}: {}", device.path.to_string_lossy(), err);
+                continue;
+            }
+        };
+        let axes: Vec<(Axis, i32, i32)> = Axis::ABSOLUTE
+            .iter()
+            .filter_map(|axis| {
+                let info = evdev_device.abs_info(&axis_to_event_code(axis))?;
+                return Some((*axis, info.minimum, info.maximum));
+            })
+            .collect();
+        if axes.is_empty() {
+            println!(
+                "Skipping {}: It has no absolute axes",
+                device.path.to_string_lossy()
+            );
+            continue;
+        }
+
+        let name = config_name(device, &mut used_names);
+        println!("Axes of {}:", name);
+        for (index, (axis, min, max)) in axes.iter().enumerate() {
+            println!(
+                "  {}: {:<8} {} .. {}",
+                index + 1,
+                axis.to_string(),
+                min,
+                max
+            );
+        }
+        let chosen_axes = prompt_selection("Axes to include", axes.len())?;
+
+        selected.push(SelectedDevice {
+            name,
+            evdev_name: device.name.clone().unwrap_or_default(),
+            matcher: suggest_matcher(device, &devices),
+            axes: chosen_axes.into_iter().map(|index| axes[index]).collect(),
+        });
+    }
+
+    // Empty sections would not even parse.
+    if selected.is_empty() {
+        return Err(no_joysticks());
+    }
+
+    fs::write(&config_path, config_yaml(&selected)).map_err(|source| ConfigError::Io {
+        path: config_path.clone(),
+        source,
+    })?;
+    println!(
+        "Wrote {}. Run `pimp-my-axis monitor` to check it.",
+        config_path.to_string_lossy()
+    );
+    return Ok(());
+}
+
+/// The error when there is no joystick to write a config for, so that scripts can tell that none
+/// was written.
+fn no_joysticks() -> Error {
+    return DeviceError::NotFound {
+        device: "Joysticks".to_owned(),
+        message: "Found none which can be used, so no config was written. Check that they are \
+                  plugged in and that you may read them."
+            .to_owned(),
+    }
+    .into();
+}
+
+/// Asks for a list of numbers from 1 to `count` until a valid one is entered and returns them as
+/// indices. An empty answer selects everything.
+fn prompt_selection(prompt: &str, count: usize) -> Result<Vec<usize>, RuntimeError> {
+    loop {
+        print!("{} (e.g. 1,3) [all]: ", prompt);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok((0..count).collect());
+        }
+        match parse_selection(&line, count) {
+            Ok(indices) => return Ok(indices),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+fn parse_selection(input: &str, count: usize) -> Result<Vec<usize>, String> {
+    let mut indices = Vec::new();
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        if part.is_empty() {
+            continue;
+        }
+        let index = match part.parse::<usize>() {
+            Ok(number) if (1..=count).contains(&number) => number - 1,
+            _ => return Err(format!("'{}' is not a number from 1 to {}", part, count)),
+        };
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+    }
+
+    if indices.is_empty() {
+        return Ok((0..count).collect());
+    }
+    return Ok(indices);
+}
+
+fn config_yaml(devices: &[SelectedDevice]) -> String {
+    let mut yaml = String::from("# Generated by `pimp-my-axis init`.\nreal_devices:\n");
+    for device in devices {
+        yaml.push_str(&format!("  {}:\n", device.name));
+        yaml.push_str(&matcher_yaml(&device.matcher, "    "));
+        yaml.push_str("    # Uncomment to hide the device from everything but Pimp-My-Axis.\n");
+        yaml.push_str("    # grab: true\n");
+    }
+
+    yaml.push_str("\nvirt_devices:\n");
+    for device in devices.iter().filter(|device| !device.axes.is_empty()) {
+        yaml.push_str(&format!("  {}:\n", device.name));
+        yaml.push_str(&format!(
+            "    name: {:?}\n",
+            format!("{} (Pimp-My-Axis)", device.evdev_name)
+        ));
+        yaml.push_str("    axes:\n");
+        for (axis, min, max) in &device.axes {
+            yaml.push_str(&format!("      {}:\n", axis));
+            yaml.push_str(&format!("        min: {}\n", min));
+            yaml.push_str(&format!("        max: {}\n", max));
+            yaml.push_str(&format!("        expr: \"{}:{}\"\n", device.name, axis));
+        }
+    }
+    return yaml;
+}
+
+#[cfg(test)]
+mod tests {
+    use pimp_my_axis::AxisExpression;
+    use pimp_my_axis::config::{Axis, Config, RealDeviceMatcher};
+
+    use crate::init::{config_yaml, parse_selection, SelectedDevice};
+
+    #[test]
+    fn generated_config_passes_axes_through() {
+        let devices = vec![SelectedDevice {
+            name: "pedals".to_owned(),
+            evdev_name: "Generic Pedals".to_owned(),
+            matcher: RealDeviceMatcher {
+                vendor_id: Some(0x06a3),
+                product_id: Some(0x0763),
+                ..RealDeviceMatcher::default()
+            },
+            axes: vec![(Axis::RZ, 0, 255), (Axis::X, -512, 511)],
+        }];
+
+        let config: Config = serde_yaml::from_str(&config_yaml(&devices)).unwrap();
+        assert_eq!(
+            config.real_devices["pedals"].matcher.product_id,
+            Some(0x0763)
+        );
+        let virt_device = &config.virt_devices["pedals"];
+        assert_eq!(virt_device.name, "Generic Pedals (Pimp-My-Axis)");
+        assert_eq!(virt_device.axes[&Axis::X].min, -512);
+        assert_eq!(
+            virt_device.axes[&Axis::RZ].expr,
+            AxisExpression::AxisReference("pedals".to_owned(), Axis::RZ)
+        );
+    }
+
+    #[test]
+    fn selection_is_one_based() {
+        assert_eq!(parse_selection("\n", 3), Ok(vec![0, 1, 2]));
+        assert_eq!(parse_selection("3, 1 3\n", 3), Ok(vec![2, 0]));
+        assert!(parse_selection("4", 3).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Maps the axes of real input devices to virtual ones through expressions. Besides the
+//! `pimp-my-axis` binary, the mapping engine can be embedded: parse a `Config`, set up a `Mapper`
+//! and either let it read the real devices with `start` and `process`, or provide the values
+//! through your own `DeviceSource` implementations and `Mapper::push_frame`.
+
+#![allow(clippy::needless_return)]
+
+extern crate evdev_rs;
+
+pub use crate::config::{Config, parse_config};
+pub use crate::error::Error;
+pub use crate::expr::{AxisExpression, parse_expr};
+pub use crate::mapper::Mapper;
+pub use crate::real::{AxisUpdate, EventDeviceInfo, Frame, RawEvent};
+pub use crate::source::{AbsAxisInfo, DeviceIdentity, DeviceSource};
+
+pub mod config;
+pub mod error;
+mod event_loop;
+pub mod expr;
+mod hotplug;
+pub mod mapper;
+mod real;
+pub mod source;
+mod uinput;
+mod virt;
+
+/// Access to the real devices for the subcommands of the `pimp-my-axis` binary, which is not part of
+/// the embedding API.
+#[doc(hidden)]
+pub mod cli {
+    pub use crate::real::{
+        axis_to_event_code, event_code_to_axis, event_codes, get_event_devices, open_event_device,
+        RealDevice,
+    };
+}
@@ -0,0 +1,289 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashSet;
+
+use evdev_rs::DeviceWrapper;
+use evdev_rs::enums::EventCode;
+use regex::Regex;
+
+use pimp_my_axis::EventDeviceInfo;
+use pimp_my_axis::cli::{event_code_to_axis, event_codes, get_event_devices, open_event_device};
+use pimp_my_axis::config::{Pattern, RealDeviceMatcher};
+use pimp_my_axis::error::DeviceError;
+
+/// Prints all event devices along with their capabilities, followed by a `real_devices` section
+/// matching those of them which have axes.
+pub fn list_devices() -> Result<(), DeviceError> {
+    let mut devices = get_event_devices()?;
+    devices.sort_by_key(|device| (device.path.as_os_str().len(), device.path.clone()));
+
+    let mut snippet = String::from("real_devices:\n");
+    let mut used_names = HashSet::new();
+
+    for device in &devices {
+        println!("{}", device.path.to_string_lossy());
+        println!(
+            "  IDs:       {:04x}:{:04x}",
+            device.vendor_id.unwrap_or(0),
+            device.product_id.unwrap_or(0)
+        );
+        println!("  Name:      {}", device.name.as_deref().unwrap_or(""));
+        println!("  Phys:      {}", device.phys.as_deref().unwrap_or(""));
+        println!("  Uniq:      {}", device.uniq.as_deref().unwrap_or(""));
+        println!(
+            "  Port path: {}",
+            device.port_path().map(String::as_str).unwrap_or("")
+        );
+
+        let evdev_device = match open_event_device(&device.path) {
+            Ok(evdev_device) => evdev_device,
+            Err(err) => {
+                println!("  Unable to open device: {}", err);
+                println!();
+                continue;
+            }
+        };
+
+        let supported: Vec<EventCode> = event_codes()
+            .filter(|code| evdev_device.has_event_code(code))
+            .collect();
+        let abs_codes = of_type(&supported, |code| matches!(code, EventCode::EV_ABS(_)));
+        let rel_codes = of_type(&supported, |code| matches!(code, EventCode::EV_REL(_)));
+        let key_codes = of_type(&supported, |code| matches!(code, EventCode::EV_KEY(_)));
+
+        if !abs_codes.is_empty() {
+            println!("  Absolute axes:");
+            for code in &abs_codes {
+                let info = evdev_device.abs_info(code).unwrap();
+                println!(
+                    "    {:<16} {:>7} .. {:<7} fuzz {}, flat {}, resolution {}",
+                    describe_code(code),
+                    info.minimum,
+                    info.maximum,
+                    info.fuzz,
+                    info.flat,
+                    info.resolution
+                );
+            }
+        }
+        if !rel_codes.is_empty() {
+            println!("  Relative axes:");
+            for code in &rel_codes {
+                println!("    {}", describe_code(code));
+            }
+        }
+        if !key_codes.is_empty() {
+            println!("  Keys:");
+            for line in key_codes.chunks(6) {
+                let names: Vec<String> = line.iter().map(|code| code.to_string()).collect();
+                println!("    {}", names.join(", "));
+            }
+        }
+        println!();
+
+        if abs_codes
+            .iter()
+            .chain(&rel_codes)
+            .any(|code| event_code_to_axis(code).is_some())
+        {
+            let name = config_name(device, &mut used_names);
+            snippet.push_str(&format!("  {}:\n", name));
+            snippet.push_str(&matcher_yaml(&suggest_matcher(device, &devices), "    "));
+        }
+    }
+
+    println!("# Matchers for all devices with axes, ready to paste into the config file:");
+    print!("{}", snippet);
+    return Ok(());
+}
+
+fn of_type(codes: &[EventCode], is_type: impl Fn(&EventCode) -> bool) -> Vec<EventCode> {
+    return codes.iter().copied().filter(is_type).collect();
+}
+
+/// The kernel name of an event code, along with the axis name used in expressions, if any.
+fn describe_code(code: &EventCode) -> String {
+    return match event_code_to_axis(code) {
+        Some(axis) => format!("{} ({:?})", code, axis),
+        None => code.to_string(),
+    };
+}
+
+/// Builds a matcher which selects the given device and no other, preferring criteria which stay
+/// the same when it is plugged into another port.
+pub fn suggest_matcher(device: &EventDeviceInfo, devices: &[EventDeviceInfo]) -> RealDeviceMatcher {
+    let mut matcher = RealDeviceMatcher {
+        vendor_id: device.vendor_id,
+        product_id: device.product_id,
+        ..RealDeviceMatcher::default()
+    };
+    if matcher.is_empty() {
+        matcher.name = device.name.clone();
+    }
+
+    let refinements: [&dyn Fn(&mut RealDeviceMatcher); 3] = [
+        &|matcher| matcher.name = device.name.clone(),
+        &|matcher| matcher.uniq = device.uniq.clone(),
+        &|matcher| matcher.port_path = device.port_path().cloned(),
+    ];
+    for refine in &refinements {
+        if matcher.candidates(devices).len() <= 1 {
+            return matcher;
+        }
+        refine(&mut matcher);
+    }
+
+    // An index alone does not identify anything, and an empty matcher is rejected.
+    if matcher.is_empty() {
+        return location_matcher(device, devices);
+    }
+
+    let candidates = matcher.candidates(devices);
+    if candidates.len() > 1 {
+        matcher.index = candidates
+            .iter()
+            .position(|candidate| candidate.path == device.path);
+    }
+    return matcher;
+}
+
+/// Matches a device which reports nothing identifying by its link in `/dev/input/by-id`, or
+/// failing that by its path, which may change when it is reconnected.
+fn location_matcher(device: &EventDeviceInfo, devices: &[EventDeviceInfo]) -> RealDeviceMatcher {
+    for name in device.by_id_names() {
+        let matcher = RealDeviceMatcher {
+            by_id: Some(Pattern(
+                Regex::new(&format!("^{}$", regex::escape(&name))).unwrap(),
+            )),
+            ..RealDeviceMatcher::default()
+        };
+        if matcher.candidates(devices).len() == 1 {
+            return matcher;
+        }
+    }
+    return RealDeviceMatcher {
+        path: Some(device.path.clone()),
+        ..RealDeviceMatcher::default()
+    };
+}
+
+pub fn matcher_yaml(matcher: &RealDeviceMatcher, indent: &str) -> String {
+    let mut yaml = String::new();
+    if let Some(path) = &matcher.path {
+        yaml.push_str(&format!("{}path: {:?}\n", indent, path));
+    }
+    if let Some(vendor_id) = matcher.vendor_id {
+        yaml.push_str(&format!("{}vendor_id: 0x{:04x}\n", indent, vendor_id));
+    }
+    if let Some(product_id) = matcher.product_id {
+        yaml.push_str(&format!("{}product_id: 0x{:04x}\n", indent, product_id));
+    }
+    if let Some(name) = &matcher.name {
+        yaml.push_str(&format!("{}name: {:?}\n", indent, name));
+    }
+    if let Some(uniq) = &matcher.uniq {
+        yaml.push_str(&format!("{}uniq: {:?}\n", indent, uniq));
+    }
+    if let Some(Pattern(regex)) = &matcher.by_id {
+        yaml.push_str(&format!("{}by_id: {:?}\n", indent, regex.as_str()));
+    }
+    if let Some(port_path) = &matcher.port_path {
+        yaml.push_str(&format!("{}port_path: {:?}\n", indent, port_path));
+    }
+    if let Some(index) = matcher.index {
+        yaml.push_str(&format!("{}index: {}\n", indent, index));
+    }
+    return yaml;
+}
+
+/// Derives a name for the device to be used in the config file from its evdev name.
+pub fn config_name(device: &EventDeviceInfo, used_names: &mut HashSet<String>) -> String {
+    let base = device
+        .name
+        .as_deref()
+        .unwrap_or("device")
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_");
+
+    let mut name = base.clone();
+    let mut suffix = 2;
+    while !used_names.insert(name.clone()) {
+        name = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+    return name;
+}
+
+#[cfg(test)]
+mod tests {
+    use pimp_my_axis::EventDeviceInfo;
+
+    use crate::list_devices::{matcher_yaml, suggest_matcher};
+
+    #[test]
+    fn suggested_matcher_is_unambiguous() {
+        let device = |path: &str, name: &str, uniq: &str| EventDeviceInfo {
+            path: path.into(),
+            vendor_id: Some(0x06a3),
+            product_id: Some(0x0763),
+            name: Some(name.to_owned()),
+            uniq: Some(uniq.to_owned()),
+            ..EventDeviceInfo::default()
+        };
+        let devices = vec![
+            device("/dev/input/event3", "Saitek Pro Flight Rudder Pedals", "A"),
+            device("/dev/input/event4", "Saitek Pro Flight Rudder Pedals", "B"),
+            device("/dev/input/event5", "Saitek Pro Flight Rudder Pedals", "B"),
+        ];
+
+        let matcher = suggest_matcher(&devices[0], &devices);
+        assert_eq!(matcher.uniq.as_deref(), Some("A"));
+        assert_eq!(matcher.index, None);
+
+        let matcher = suggest_matcher(&devices[2], &devices);
+        assert_eq!(matcher.index, Some(1));
+        assert_eq!(matcher.select(&devices).unwrap().path, devices[2].path);
+    }
+
+    #[test]
+    fn anonymous_device_is_matched_by_location() {
+        let devices = vec![
+            EventDeviceInfo {
+                path: "/dev/input/event3".into(),
+                links: vec!["/dev/input/by-id/usb-Generic_Pedals-event-joystick".into()],
+                ..EventDeviceInfo::default()
+            },
+            EventDeviceInfo {
+                path: "/dev/input/event4".into(),
+                ..EventDeviceInfo::default()
+            },
+        ];
+
+        let matcher = suggest_matcher(&devices[0], &devices);
+        assert!(matcher.by_id.is_some());
+        assert_eq!(matcher.index, None);
+        assert_eq!(matcher.select(&devices).unwrap().path, devices[0].path);
+
+        let matcher = suggest_matcher(&devices[1], &devices);
+        assert!(matcher.is_path_only());
+        assert_eq!(matcher_yaml(&matcher, ""), "path: \"/dev/input/event4\"\n");
+    }
+}
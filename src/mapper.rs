@@ -0,0 +1,1037 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::slice;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, trace, warn};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use crate::config::{Axis, AxisConfig, Config, VirtDeviceConfig};
+use crate::error::{ConfigError, Error, ExpressionError, RuntimeError};
+use crate::event_loop::EventLoop;
+use crate::expr::AxisExpression;
+use crate::hotplug::Hotplug;
+use crate::real::{Frame, get_event_devices, RawEvent, RealAxis, RealDevice};
+use crate::source::DeviceSource;
+use crate::virt::{VirtAxis, VirtDevice};
+
+/// How often relative axes decay, mice move and keys pulse.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a file descriptor watched by the mapper belongs to.
+#[derive(Clone, Debug)]
+enum Source {
+    RealDevice(String),
+    Hotplug,
+    Timer,
+    Signal,
+    /// Added through `watch` and handled by the caller of `process`.
+    External(RawFd),
+}
+
+/// Connects real devices to virtual ones by evaluating the configured expressions whenever a real
+/// axis changes.
+pub struct Mapper {
+    real_devices: HashMap<String, Box<dyn DeviceSource>>,
+    virt_devices: HashMap<String, VirtDevice>,
+    virt_axes: HashMap<(String, Axis), VirtAxis>,
+    real_axes: HashMap<(String, Axis), RealAxis>,
+    event_loop: EventLoop<Source>,
+    /// Whether the real devices are found through udev, which is also watched for devices being
+    /// plugged in.
+    uses_udev: bool,
+    hotplug: Option<Hotplug>,
+    timer: Option<TimerFd>,
+    last_tick: Instant,
+    signals: Option<SignalFd>,
+    stopping: bool,
+}
+
+impl Mapper {
+    /// Sets up all devices from the config. The uinput devices are only created if `output` is
+    /// set. Nothing is read from the real devices until `start` has been called.
+    pub fn new(config: &Config, output: bool) -> Result<Mapper, Error> {
+        let mut mapper = Mapper::with_sources(config, real_devices(config), output)?;
+        mapper.uses_udev = true;
+        return Ok(mapper);
+    }
+
+    /// Sets up the virtual devices from the config, reading the real axes from the given sources
+    /// instead of the real devices in the config. Udev is not used then, so the sources are
+    /// connected without any available event devices and are not connected on hotplug.
+    pub fn with_sources(
+        config: &Config,
+        real_devices: HashMap<String, Box<dyn DeviceSource>>,
+        output: bool,
+    ) -> Result<Mapper, Error> {
+        let virt_devices = virt_devices(config, &real_devices, output)?;
+        let virt_axes = virt_axes(&virt_devices);
+        let real_axes = real_axes(&real_devices, &virt_axes)?;
+
+        return Ok(Mapper {
+            real_devices,
+            virt_devices,
+            virt_axes,
+            real_axes,
+            event_loop: EventLoop::new()?,
+            uses_udev: false,
+            hotplug: None,
+            timer: None,
+            last_tick: Instant::now(),
+            signals: None,
+            stopping: false,
+        });
+    }
+
+    /// Connects all available real devices and, if created with `new`, starts watching for new
+    /// ones. Also starts the timer if any relative axes decay, or any virtual devices have mouse
+    /// axes or pulsed keys.
+    pub fn start(&mut self) {
+        self.connect_devices();
+        self.create_pending_devices();
+        // Publishes the current position of every axis instead of waiting for it to move.
+        let all_axes: Vec<(String, Axis)> = self.virt_axes.keys().cloned().collect();
+        self.check_ranges(&all_axes);
+        self.update_virt_axes(all_axes);
+        self.sync_virt_devices();
+
+        let needs_ticks = self.real_devices.values().any(|device| device.has_decay())
+            || self.virt_devices.values().any(|device| {
+                return !device.config.mouse.is_empty() || !device.config.pulses.is_empty();
+            });
+        if needs_ticks {
+            match self.start_timer() {
+                Ok(timer) => self.timer = Some(timer),
+                Err(err) => error!("Unable to start the timer: {}", err),
+            }
+        }
+
+        if !self.uses_udev {
+            return;
+        }
+        match Hotplug::new().and_then(|hotplug| {
+            self.event_loop.add(hotplug.as_raw_fd(), Source::Hotplug)?;
+            return Ok(hotplug);
+        }) {
+            Ok(hotplug) => self.hotplug = Some(hotplug),
+            Err(err) => error!("{}, hotplugging is disabled", err),
+        }
+    }
+
+    /// Blocks SIGINT and SIGTERM on the calling thread, such that they are received by `process`
+    /// instead, after which `is_stopping` returns true. Programs which handle signals themselves
+    /// should not call this.
+    pub fn handle_signals(&mut self) -> Result<(), RuntimeError> {
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGINT);
+        mask.add(Signal::SIGTERM);
+        mask.thread_block()
+            .map_err(|err| RuntimeError::system("Unable to block signals", err))?;
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+            .map_err(|err| RuntimeError::system("Unable to create signalfd", err))?;
+        self.event_loop.add(signals.as_raw_fd(), Source::Signal)?;
+        self.signals = Some(signals);
+        return Ok(());
+    }
+
+    /// Whether SIGINT or SIGTERM was received, after which `shutdown` should be called.
+    pub fn is_stopping(&self) -> bool {
+        return self.stopping;
+    }
+
+    /// The real device with the given name.
+    pub fn real_device(&self, name: &str) -> Option<&dyn DeviceSource> {
+        return self.real_devices.get(name).map(|device| device.as_ref());
+    }
+
+    /// All real axes which virtual axes depend on, as pairs of device name and axis.
+    pub fn real_axes(&self) -> impl Iterator<Item = &(String, Axis)> {
+        return self.real_axes.keys();
+    }
+
+    /// All virtual axes as pairs of device name and axis, including those of buttons, hats, mouse
+    /// axes and pulsed keys.
+    pub fn virt_axes(&self) -> impl Iterator<Item = &(String, Axis)> {
+        return self.virt_axes.keys();
+    }
+
+    /// The config of a virtual axis. Buttons, hats, mouse axes and pulsed keys get one derived from
+    /// their own config.
+    pub fn virt_axis_config(&self, device: &str, axis: Axis) -> Option<&AxisConfig> {
+        return self
+            .virt_axes
+            .get(&(device.to_owned(), axis))
+            .map(|virt_axis| &virt_axis.config);
+    }
+
+    pub fn virt_device_config(&self, device: &str) -> Option<&VirtDeviceConfig> {
+        return self.virt_devices.get(device).map(|device| &device.config);
+    }
+
+    /// The value last written to an axis of a virtual device.
+    pub fn virt_value(&self, device: &str, axis: Axis) -> Option<i32> {
+        return self.virt_devices.get(device)?.value(&axis);
+    }
+
+    /// How many `SYN_REPORT`s a virtual device sent, or would have sent if there was output.
+    pub fn report_count(&self, device: &str) -> u64 {
+        return self
+            .virt_devices
+            .get(device)
+            .map_or(0, |virt_device| virt_device.report_count());
+    }
+
+    /// How many values of the expression of a virtual axis were out of its range.
+    pub fn out_of_range_count(&self, device: &str, axis: Axis) -> u64 {
+        return self
+            .virt_devices
+            .get(device)
+            .map_or(0, |virt_device| virt_device.out_of_range_count(&axis));
+    }
+
+    /// Parks all virtual axes, releases the real devices and destroys the uinput devices.
+    pub fn shutdown(mut self) {
+        for virt_device in self.virt_devices.values_mut() {
+            if let Err(err) = virt_device.park() {
+                warn!(
+                    "Unable to park virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+        self.sync_virt_devices();
+
+        for real_device in self.real_devices.values_mut() {
+            real_device.disconnect();
+        }
+        self.virt_devices.clear();
+        info!("Shut down");
+    }
+
+    fn start_timer(&mut self) -> Result<TimerFd, RuntimeError> {
+        let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_CLOEXEC)
+            .map_err(|err| RuntimeError::system("Unable to create timer", err))?;
+        timer
+            .set(
+                Expiration::Interval(TimeSpec::from(TICK_INTERVAL)),
+                TimerSetTimeFlags::empty(),
+            )
+            .map_err(|err| RuntimeError::system("Unable to set timer", err))?;
+        self.event_loop.add(timer.as_raw_fd(), Source::Timer)?;
+        self.last_tick = Instant::now();
+        return Ok(timer);
+    }
+
+    /// Also waits for the given file descriptor in `process`.
+    pub fn watch(&mut self, fd: RawFd) -> Result<(), RuntimeError> {
+        return self.event_loop.add(fd, Source::External(fd));
+    }
+
+    /// Waits until any real device, udev or the timer has something to handle, or the timeout
+    /// passes, and handles it. Returns the file descriptors added with `watch` which are readable.
+    pub fn process(&mut self, timeout: Option<Duration>) -> Result<Vec<RawFd>, RuntimeError> {
+        let mut external = Vec::new();
+        for source in self.event_loop.wait(timeout)? {
+            trace!("Handling {:?}.", source);
+            match source {
+                Source::RealDevice(name) => {
+                    match self.real_devices.get_mut(&name).unwrap().read_frames() {
+                        Some(frames) => {
+                            // Each frame is reported on its own, so that none of its values are
+                            // lost or applied together with those of the next frame.
+                            for frame in frames {
+                                self.handle_frame(frame);
+                                self.sync_virt_devices();
+                            }
+                        }
+                        None => self.handle_disconnect(&name),
+                    }
+                }
+                Source::Hotplug => {
+                    if self.hotplug.as_mut().is_some_and(Hotplug::device_added) {
+                        let connected = self.connect_devices();
+                        self.create_pending_devices();
+                        for name in connected {
+                            let affected = self.downstream_of(&name);
+                            self.check_ranges(&affected);
+                            self.update_virt_axes(affected);
+                        }
+                    }
+                }
+                Source::Timer => self.handle_tick(),
+                Source::Signal => self.read_signals(),
+                Source::External(fd) => external.push(fd),
+            }
+        }
+
+        // Reports what the timer, hotplugging and disconnects changed.
+        self.sync_virt_devices();
+        return Ok(external);
+    }
+
+    /// Handles a frame as if it had been read from a real device and writes the virtual axes
+    /// depending on it.
+    pub fn push_frame(&mut self, frame: Frame) {
+        self.handle_frame(frame);
+        self.sync_virt_devices();
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        // Each virtual axis is evaluated once with all values of the frame.
+        let mut axis_values = HashMap::<(String, Axis), i32>::new();
+        let mut affected: Vec<(String, Axis)> = Vec::new();
+        for update in frame.axes {
+            match self.real_axes.get(&(update.device.clone(), update.axis)) {
+                Some(real_axis) => {
+                    for downstream in &real_axis.downstream {
+                        if !affected.contains(downstream) {
+                            affected.push(downstream.clone());
+                        }
+                    }
+                }
+                None => debug!(
+                    "Ignoring update for axis {}:{} which is not used",
+                    update.device, update.axis
+                ),
+            }
+            axis_values.insert((update.device, update.axis), update.new_value);
+        }
+
+        for key in affected {
+            update_virt_axis(
+                &self.virt_axes[&key],
+                &mut axis_values,
+                &self.real_devices,
+                &mut self.virt_devices,
+            );
+        }
+        for event in &frame.events {
+            self.forward(event);
+        }
+    }
+
+    fn handle_disconnect(&mut self, name: &str) {
+        let device = self.real_devices.get_mut(name).unwrap();
+        if let Some(fd) = device.raw_fd() {
+            self.event_loop.remove(fd);
+        }
+        device.disconnect();
+
+        // Events passed through from the device return to rest, just like the axes depending on it.
+        for virt_device in self.virt_devices.values_mut() {
+            let passes_through = virt_device
+                .config
+                .passthrough
+                .as_ref()
+                .is_some_and(|passthrough| passthrough.device == name);
+            if !passes_through {
+                continue;
+            }
+            if let Err(err) = virt_device.park_passthrough() {
+                warn!(
+                    "Unable to reset virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+        let affected = self.downstream_of(name);
+        self.update_virt_axes(affected);
+    }
+
+    /// Returns the virtual axes which depend on any axis of the named real device.
+    fn downstream_of(&self, name: &str) -> Vec<(String, Axis)> {
+        let mut affected: Vec<(String, Axis)> = Vec::new();
+        for real_axis in self
+            .real_axes
+            .values()
+            .filter(|real_axis| real_axis.device == name)
+        {
+            for downstream in &real_axis.downstream {
+                if !affected.contains(downstream) {
+                    affected.push(downstream.clone());
+                }
+            }
+        }
+        return affected;
+    }
+
+    /// Warns about virtual axes whose expression can produce values outside of their range, as far
+    /// as the ranges of the real axes they depend on are known, and passes the values their
+    /// expression can produce on to their devices for scaling.
+    fn check_ranges(&mut self, keys: &[(String, Axis)]) {
+        let real_devices = &self.real_devices;
+        for key in keys {
+            let virt_axis = &self.virt_axes[key];
+            let virt_device = self.virt_devices.get_mut(&virt_axis.device).unwrap();
+            // Buttons, hats, mouse axes and pulsed keys handle any value.
+            if !virt_device.config.axes.contains_key(&virt_axis.axis) {
+                continue;
+            }
+
+            let ranges = virt_axis
+                .config
+                .expr
+                .dependencies()
+                .into_iter()
+                .filter_map(|(dev, axis)| {
+                    let range = real_devices[&dev].range(&axis)?;
+                    return Some(((dev, axis), range));
+                })
+                .collect();
+            let bounds = virt_axis.config.expr.bounds(&ranges);
+            virt_device.set_expression_bounds(&virt_axis.axis, bounds);
+
+            let config = &virt_axis.config;
+            match bounds {
+                Some((low, high)) if low < config.min as i64 || high > config.max as i64 => warn!(
+                    "Virtual axis {} can get values from {} to {}, outside of its range {}..{}, which are handled by {:?}",
+                    virt_axis, low, high, config.min, config.max, config.out_of_range
+                ),
+                Some(_) => {}
+                None => debug!(
+                    "The values virtual axis {} can get are unknown",
+                    virt_axis
+                ),
+            }
+        }
+    }
+
+    /// Evaluates the given virtual axes with the current values of the real devices.
+    fn update_virt_axes(&mut self, keys: Vec<(String, Axis)>) {
+        let mut axis_values = HashMap::new();
+        for key in keys {
+            update_virt_axis(
+                &self.virt_axes[&key],
+                &mut axis_values,
+                &self.real_devices,
+                &mut self.virt_devices,
+            );
+        }
+    }
+
+    fn handle_tick(&mut self) {
+        if let Some(timer) = &self.timer {
+            // Reads the number of expirations, which is not needed since the time is measured.
+            let _ = timer.wait();
+        }
+        let now = Instant::now();
+        let elapsed = now - self.last_tick;
+        self.last_tick = now;
+
+        let mut decayed = Frame::default();
+        for device in self.real_devices.values_mut() {
+            decayed.axes.append(&mut device.decay(elapsed));
+        }
+        if !decayed.axes.is_empty() {
+            trace!("Applying decay updates {:?}.", decayed.axes);
+            self.handle_frame(decayed);
+        }
+
+        for virt_device in self.virt_devices.values_mut() {
+            if let Err(err) = virt_device.tick(elapsed) {
+                warn!(
+                    "Unable to update virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+    }
+
+    fn read_signals(&mut self) {
+        let signals = match &mut self.signals {
+            Some(signals) => signals,
+            None => return,
+        };
+        while let Ok(Some(info)) = signals.read_signal() {
+            match Signal::try_from(info.ssi_signo as i32) {
+                Ok(signal) => info!("Received {}, shutting down", signal),
+                Err(_) => info!("Received signal {}, shutting down", info.ssi_signo),
+            }
+            self.stopping = true;
+        }
+    }
+
+    fn forward(&mut self, event: &RawEvent) {
+        for virt_device in self.virt_devices.values_mut() {
+            if let Err(err) = virt_device.forward(&event.device, &event.event_code, event.value) {
+                warn!(
+                    "Unable to pass through event to virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+    }
+
+    /// Ends the frame of every virtual device which changed.
+    fn sync_virt_devices(&mut self) {
+        for virt_device in self.virt_devices.values_mut() {
+            if let Err(err) = virt_device.sync() {
+                warn!(
+                    "Unable to synchronize virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+    }
+
+    /// Tries to connect all real devices which are currently disconnected and watches the ones
+    /// that succeed. Returns the names of those.
+    fn connect_devices(&mut self) -> Vec<String> {
+        // Sources given to `with_sources` find their devices themselves.
+        let available_devices = if self.uses_udev {
+            match get_event_devices() {
+                Ok(available_devices) => available_devices,
+                Err(err) => {
+                    warn!("Unable to list the available devices: {}", err);
+                    return Vec::new();
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let mut connected = Vec::new();
+
+        for device in self.real_devices.values_mut() {
+            if device.is_connected() {
+                continue;
+            }
+            let name = device.name().to_owned();
+            if let Err(err) = device.connect(&available_devices) {
+                info!("Device {} is not available: {}", name, err);
+                continue;
+            }
+
+            for real_axis in self
+                .real_axes
+                .values()
+                .filter(|real_axis| real_axis.device == name)
+            {
+                if !device.supports(&real_axis.axis) {
+                    warn!(
+                        "Device '{}' does not support axis '{:?}'",
+                        name, real_axis.axis
+                    );
+                }
+            }
+
+            // Sources without a file descriptor are fed through `push_frame`.
+            if let Some(fd) = device.raw_fd() {
+                if let Err(err) = self.event_loop.add(fd, Source::RealDevice(name.clone())) {
+                    warn!("Unable to read from device {}: {}", name, err);
+                    device.disconnect();
+                    continue;
+                }
+            }
+            connected.push(name);
+        }
+        return connected;
+    }
+
+    /// Creates the uinput devices of virtual devices passing through or cloning real devices which
+    /// have become available, since their capabilities are only known then.
+    fn create_pending_devices(&mut self) {
+        let real_devices = &self.real_devices;
+        for virt_device in self.virt_devices.values_mut() {
+            let names: Vec<String> = virt_device
+                .pending_sources()
+                .into_iter()
+                .map(str::to_owned)
+                .collect();
+            if names.is_empty() {
+                continue;
+            }
+
+            let sources: HashMap<&str, &dyn DeviceSource> = names
+                .iter()
+                .map(|name| (name.as_str(), real_devices[name].as_ref()))
+                .filter(|(_, device)| device.is_connected())
+                .collect();
+            if sources.len() < names.len() {
+                continue;
+            }
+
+            if let Err(err) = virt_device.create(&sources) {
+                warn!(
+                    "Unable to create virtual device {}: {}",
+                    virt_device.name, err
+                );
+            }
+        }
+    }
+
+    /// Replaces the expression of a virtual axis and writes its new value. The new expression must
+    /// reference the same real axes as the old one.
+    pub fn set_expression(
+        &mut self,
+        device: &str,
+        axis: Axis,
+        expr: AxisExpression,
+    ) -> Result<(), Error> {
+        let virt_axis = self
+            .virt_axes
+            .get_mut(&(device.to_owned(), axis))
+            .ok_or_else(|| {
+                ConfigError::invalid(format!("Virtual axis {}:{}", device, axis), "Not defined")
+            })?;
+        if expr.dependencies() != virt_axis.config.expr.dependencies() {
+            return Err(ExpressionError::DependenciesChanged {
+                old: virt_axis.config.expr.to_string(),
+                new: expr.to_string(),
+            }
+            .into());
+        }
+        virt_axis.config.expr = expr;
+
+        let key = (device.to_owned(), axis);
+        self.check_ranges(slice::from_ref(&key));
+        update_virt_axis(
+            &self.virt_axes[&key],
+            &mut HashMap::new(),
+            &self.real_devices,
+            &mut self.virt_devices,
+        );
+        self.sync_virt_devices();
+        return Ok(());
+    }
+}
+
+fn virt_devices(
+    config: &Config,
+    real_devices: &HashMap<String, Box<dyn DeviceSource>>,
+    output: bool,
+) -> Result<HashMap<String, VirtDevice>, Error> {
+    let mut result = HashMap::new();
+    for (name, dev_config) in &config.virt_devices {
+        let context = format!("Virtual device '{}'", name);
+        if let Some(passthrough) = &dev_config.passthrough {
+            if !real_devices.contains_key(&passthrough.device) {
+                return Err(ConfigError::invalid(
+                    context,
+                    format!(
+                        "Passes through device '{}' which is not defined",
+                        passthrough.device
+                    ),
+                )
+                .into());
+            }
+        }
+        if let Some(clone) = &dev_config.clone {
+            if !real_devices.contains_key(&clone.device) {
+                return Err(ConfigError::invalid(
+                    context,
+                    format!("Clones device '{}' which is not defined", clone.device),
+                )
+                .into());
+            }
+        }
+
+        let device = if output {
+            VirtDevice::new(name.clone(), dev_config)?
+        } else {
+            VirtDevice::new_without_output(name.clone(), dev_config)?
+        };
+        result.insert(name.clone(), device);
+    }
+    return Ok(result);
+}
+
+fn virt_axes(virt_devices: &HashMap<String, VirtDevice>) -> HashMap<(String, Axis), VirtAxis> {
+    let mut result = HashMap::new();
+    for (name, device) in virt_devices {
+        for (axis, axis_config) in &device.config.axes {
+            let virt_axis = VirtAxis::new(name.clone(), *axis, axis_config.clone());
+            result.insert((name.clone(), *axis), virt_axis);
+        }
+        // Buttons are released while a real device they depend on is disconnected.
+        for (button, expr) in &device.config.buttons {
+            let button_config = AxisConfig::new(0, 1, expr.clone(), Some(0));
+            let virt_axis = VirtAxis::new(name.clone(), *button, button_config);
+            result.insert((name.clone(), *button), virt_axis);
+        }
+        // Hats return to the center instead.
+        for (hat, hat_config) in &device.config.hats {
+            let (x, y) = hat.axes();
+            let (x_expr, y_expr) = hat_config.expressions();
+            for (axis, expr) in [(x, x_expr), (y, y_expr)] {
+                let hat_axis_config = AxisConfig::new(-1, 1, expr, Some(0));
+                let virt_axis = VirtAxis::new(name.clone(), axis, hat_axis_config);
+                result.insert((name.clone(), axis), virt_axis);
+            }
+        }
+        // Mouse axes stop moving and keys stop pulsing.
+        for (axis, mouse_config) in &device.config.mouse {
+            let mouse_axis_config = AxisConfig::new(
+                -mouse_config.max,
+                mouse_config.max,
+                mouse_config.expr.clone(),
+                Some(0),
+            );
+            let virt_axis = VirtAxis::new(name.clone(), *axis, mouse_axis_config);
+            result.insert((name.clone(), *axis), virt_axis);
+        }
+        for (key, pulse_config) in &device.config.pulses {
+            let pulse_axis_config =
+                AxisConfig::new(0, pulse_config.max, pulse_config.expr.clone(), Some(0));
+            let virt_axis = VirtAxis::new(name.clone(), *key, pulse_axis_config);
+            result.insert((name.clone(), *key), virt_axis);
+        }
+    }
+    return result;
+}
+
+fn real_devices(config: &Config) -> HashMap<String, Box<dyn DeviceSource>> {
+    return config
+        .real_devices
+        .iter()
+        .map(|(name, dev_config)| {
+            let device: Box<dyn DeviceSource> = Box::new(RealDevice::new(name.clone(), dev_config));
+            return (name.clone(), device);
+        })
+        .collect();
+}
+
+/// Evaluates the expression of a virtual axis and writes the result to its device. Values of real
+/// axes which are not yet in `axis_values` are read from the real devices. If one of them is
+/// disconnected, the fallback value is written instead, if any.
+fn update_virt_axis(
+    virt_axis: &VirtAxis,
+    axis_values: &mut HashMap<(String, Axis), i32>,
+    real_devices: &HashMap<String, Box<dyn DeviceSource>>,
+    virt_devices: &mut HashMap<String, VirtDevice>,
+) {
+    let mut unavailable = None;
+    for (dep_dev, dep_axis) in virt_axis.config.expr.dependencies() {
+        if axis_values.contains_key(&(dep_dev.clone(), dep_axis)) {
+            continue;
+        }
+
+        let value = match real_devices.get(&dep_dev) {
+            Some(device) => device.read(&dep_axis),
+            None => {
+                let err = ConfigError::invalid(
+                    format!("Virtual axis {}", virt_axis),
+                    format!("References device '{}' which is not defined", dep_dev),
+                );
+                error!("Not updating virtual axis: {}", err);
+                return;
+            }
+        };
+        match value {
+            Ok(value) => {
+                axis_values.insert((dep_dev, dep_axis), value);
+            }
+            Err(err) => {
+                unavailable = Some(err);
+                break;
+            }
+        }
+    }
+
+    let new_value = match (unavailable, virt_axis.config.fallback) {
+        (None, _) => match virt_axis.config.expr.eval(axis_values) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("Unable to evaluate virtual axis {}: {}", virt_axis, err);
+                return;
+            }
+        },
+        (Some(err), Some(fallback)) => {
+            debug!(
+                "Using fallback value {} for virtual axis {}: {}",
+                fallback, virt_axis, err
+            );
+            fallback
+        }
+        (Some(err), None) => {
+            debug!("Not updating virtual axis {}: {}", virt_axis, err);
+            return;
+        }
+    };
+
+    debug!(
+        "Calculated new value {} for virtual axis {}",
+        new_value, virt_axis
+    );
+    if let Err(err) = virt_devices
+        .get_mut(&virt_axis.device)
+        .unwrap()
+        .write(&virt_axis.axis, new_value)
+    {
+        warn!("Unable to write virtual axis {}: {}", virt_axis, err);
+    }
+}
+
+fn real_axes(
+    real_devs: &HashMap<String, Box<dyn DeviceSource>>,
+    virt_axes: &HashMap<(String, Axis), VirtAxis>,
+) -> Result<HashMap<(String, Axis), RealAxis>, ConfigError> {
+    let mut result = HashMap::new();
+    for virt_axis in virt_axes.values() {
+        for (dep_dev, dep_axis) in virt_axis.config.expr.dependencies() {
+            if real_devs.contains_key(&dep_dev) {
+                let real_axis = result
+                    .entry((dep_dev.clone(), dep_axis))
+                    .or_insert_with(|| RealAxis::new(dep_dev.clone(), dep_axis));
+                let key = (virt_axis.device.clone(), virt_axis.axis);
+                if !real_axis.downstream.contains(&key) {
+                    real_axis.downstream.push(key);
+                    trace!("{}.{:?} -> {}", dep_dev, dep_axis, virt_axis)
+                }
+            } else {
+                return Err(ConfigError::invalid(
+                    format!("Virtual axis {}", virt_axis),
+                    format!("References device '{}' which is not defined", dep_dev),
+                ));
+            }
+        }
+    }
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::mem;
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    use evdev_rs::enums::{EV_ABS, EV_KEY, EventCode};
+    use nix::unistd;
+
+    use crate::config::{Axis, Config};
+    use crate::error::DeviceError;
+    use crate::mapper::Mapper;
+    use crate::real::{axis_to_event_code, AxisUpdate, EventDeviceInfo, Frame, RawEvent};
+    use crate::source::{AbsAxisInfo, DeviceSource};
+
+    struct FixedSource {
+        name: String,
+        values: HashMap<Axis, i32>,
+        connected: bool,
+        /// Polled by the mapper, if set, which then reads `frames`.
+        fd: Option<RawFd>,
+        frames: Vec<Frame>,
+    }
+
+    impl FixedSource {
+        fn new(name: &str, values: Vec<(Axis, i32)>) -> FixedSource {
+            return FixedSource {
+                name: name.to_owned(),
+                values: values.into_iter().collect(),
+                connected: false,
+                fd: None,
+                frames: Vec::new(),
+            };
+        }
+    }
+
+    impl DeviceSource for FixedSource {
+        fn name(&self) -> &str {
+            return &self.name;
+        }
+
+        fn connect(&mut self, _available_devices: &[EventDeviceInfo]) -> Result<(), DeviceError> {
+            self.connected = true;
+            return Ok(());
+        }
+
+        fn disconnect(&mut self) {
+            self.connected = false;
+        }
+
+        fn is_connected(&self) -> bool {
+            return self.connected;
+        }
+
+        fn raw_fd(&self) -> Option<RawFd> {
+            return self.fd;
+        }
+
+        fn read_frames(&mut self) -> Option<Vec<Frame>> {
+            return Some(mem::take(&mut self.frames));
+        }
+
+        fn read(&self, axis: &Axis) -> Result<i32, DeviceError> {
+            return self
+                .values
+                .get(axis)
+                .copied()
+                .ok_or_else(|| DeviceError::Unsupported {
+                    device: self.name.clone(),
+                    axis: *axis,
+                });
+        }
+
+        fn range(&self, _axis: &Axis) -> Option<(i32, i32)> {
+            return Some((-50, 50));
+        }
+
+        fn supports(&self, axis: &Axis) -> bool {
+            return self.values.contains_key(axis);
+        }
+
+        fn supported_codes(&self) -> Vec<EventCode> {
+            return self.values.keys().map(axis_to_event_code).collect();
+        }
+
+        fn abs_info(&self, _event_code: &EventCode) -> Option<AbsAxisInfo> {
+            return Some(AbsAxisInfo {
+                minimum: -50,
+                maximum: 50,
+                ..AbsAxisInfo::default()
+            });
+        }
+    }
+
+    #[test]
+    fn push_frame_updates_dependent_axes() {
+        let config: Config = serde_yaml::from_str(
+            "\
+real_devices: {}
+virt_devices:
+  out:
+    axes:
+      X:
+        min: -100
+        max: 100
+        expr: \"stick:X * 2\"
+      Y:
+        min: -100
+        max: 100
+        expr: \"stick:X + stick:Y\"
+",
+        )
+        .unwrap();
+        let stick = FixedSource::new("stick", vec![(Axis::X, 0), (Axis::Y, 5)]);
+        let mut sources: HashMap<String, Box<dyn DeviceSource>> = HashMap::new();
+        sources.insert("stick".to_owned(), Box::new(stick));
+        let mut mapper = Mapper::with_sources(&config, sources, false).unwrap();
+
+        let push = |mapper: &mut Mapper, value| {
+            mapper.push_frame(Frame {
+                axes: vec![AxisUpdate {
+                    device: "stick".to_owned(),
+                    axis: Axis::X,
+                    new_value: value,
+                }],
+                events: Vec::new(),
+            });
+        };
+        push(&mut mapper, 10);
+        assert_eq!(mapper.virt_value("out", Axis::X), Some(20));
+        assert_eq!(mapper.virt_value("out", Axis::Y), Some(15));
+
+        // Clamped to the range of the virtual axis.
+        push(&mut mapper, 60);
+        assert_eq!(mapper.virt_value("out", Axis::X), Some(100));
+    }
+
+    #[test]
+    fn each_frame_of_a_read_is_reported() {
+        let config: Config = serde_yaml::from_str(
+            "\
+real_devices: {}
+virt_devices:
+  out:
+    axes:
+      X:
+        min: -100
+        max: 100
+        expr: \"stick:X\"
+",
+        )
+        .unwrap();
+        let (read_end, write_end) = unistd::pipe().unwrap();
+        unistd::write(write_end, &[0]).unwrap();
+        let mut stick = FixedSource::new("stick", vec![(Axis::X, 0)]);
+        stick.fd = Some(read_end);
+        stick.frames = [10, 20]
+            .iter()
+            .map(|value| Frame {
+                axes: vec![AxisUpdate {
+                    device: "stick".to_owned(),
+                    axis: Axis::X,
+                    new_value: *value,
+                }],
+                events: Vec::new(),
+            })
+            .collect();
+        let mut sources: HashMap<String, Box<dyn DeviceSource>> = HashMap::new();
+        sources.insert("stick".to_owned(), Box::new(stick));
+        let mut mapper = Mapper::with_sources(&config, sources, false).unwrap();
+        mapper.start();
+
+        let reports = mapper.report_count("out");
+        mapper.process(Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(mapper.report_count("out"), reports + 2);
+        assert_eq!(mapper.virt_value("out", Axis::X), Some(20));
+
+        unistd::close(read_end).unwrap();
+        unistd::close(write_end).unwrap();
+    }
+
+    #[test]
+    fn disconnect_resets_passed_through_events() {
+        let config: Config = serde_yaml::from_str(
+            "\
+real_devices: {}
+virt_devices:
+  out:
+    passthrough:
+      device: stick
+      park:
+        ABS_THROTTLE: -50
+",
+        )
+        .unwrap();
+        let stick = FixedSource::new(
+            "stick",
+            vec![(Axis::Key(EV_KEY::BTN_TRIGGER), 0), (Axis::Throttle, 0)],
+        );
+        let mut sources: HashMap<String, Box<dyn DeviceSource>> = HashMap::new();
+        sources.insert("stick".to_owned(), Box::new(stick));
+        let mut mapper = Mapper::with_sources(&config, sources, false).unwrap();
+        mapper.start();
+
+        let event = |event_code, value| RawEvent {
+            device: "stick".to_owned(),
+            event_code,
+            value,
+        };
+        mapper.push_frame(Frame {
+            axes: Vec::new(),
+            events: vec![
+                event(EventCode::EV_KEY(EV_KEY::BTN_TRIGGER), 1),
+                event(EventCode::EV_ABS(EV_ABS::ABS_THROTTLE), 50),
+            ],
+        });
+        let trigger = Axis::Key(EV_KEY::BTN_TRIGGER);
+        assert_eq!(mapper.virt_value("out", trigger), Some(1));
+        assert_eq!(mapper.virt_value("out", Axis::Throttle), Some(50));
+
+        mapper.handle_disconnect("stick");
+        assert_eq!(mapper.virt_value("out", trigger), Some(0));
+        assert_eq!(mapper.virt_value("out", Axis::Throttle), Some(-50));
+    }
+}
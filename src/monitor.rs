@@ -0,0 +1,112 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use pimp_my_axis::{Config, Error, Mapper};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs the mapping like the daemon does, while continuously printing the values of all real
+/// and virtual axes. Unless `output` is set, no uinput devices are created and no real devices
+/// are grabbed.
+pub fn monitor(mut config: Config, output: bool) -> Result<(), Error> {
+    if !output {
+        for real_device in config.real_devices.values_mut() {
+            real_device.grab = false;
+        }
+    }
+
+    let mut mapper = Mapper::new(&config, output)?;
+    if let Err(err) = mapper.handle_signals() {
+        error!(
+            "Unable to handle signals, stopping is not graceful: {}",
+            err
+        );
+    }
+    mapper.start();
+
+    let mut last_draw: Option<Instant> = None;
+    while !mapper.is_stopping() {
+        if let Err(err) = mapper.process(Some(REFRESH_INTERVAL)) {
+            mapper.shutdown();
+            return Err(err.into());
+        }
+
+        if last_draw.is_none_or(|time| time.elapsed() >= REFRESH_INTERVAL) {
+            print!("{}", render(&mapper));
+            last_draw = Some(Instant::now());
+        }
+    }
+    mapper.shutdown();
+    return Ok(());
+}
+
+fn render(mapper: &Mapper) -> String {
+    // Move the cursor to the top left corner and clear the screen.
+    let mut out = String::from("\x1b[H\x1b[2J");
+
+    let mut real_axes: Vec<_> = mapper.real_axes().collect();
+    real_axes.sort_by_key(|(device, axis)| (device.clone(), axis.to_string()));
+    let mut virt_axes: Vec<_> = mapper.virt_axes().collect();
+    virt_axes.sort_by_key(|(device, axis)| (device.clone(), axis.to_string()));
+
+    let width = real_axes
+        .iter()
+        .chain(&virt_axes)
+        .map(|(device, axis)| device.len() + axis.to_string().len() + 1)
+        .max()
+        .unwrap_or(0);
+
+    writeln!(out, "Real axes").unwrap();
+    for (device, axis) in real_axes {
+        let name = format!("{}:{}", device, axis);
+        let value = match mapper.real_device(device).unwrap().read(axis) {
+            Ok(value) => value.to_string(),
+            Err(err) => err.to_string(),
+        };
+        writeln!(out, "  {:<width$}  {:>8}", name, value, width = width).unwrap();
+    }
+
+    writeln!(out, "\nVirtual axes").unwrap();
+    for (device, axis) in virt_axes {
+        let config = mapper.virt_axis_config(device, *axis).unwrap();
+        let value = match mapper.virt_value(device, *axis) {
+            Some(value) => value.to_string(),
+            None => "-".to_owned(),
+        };
+        write!(
+            out,
+            "  {:<width$}  {:>8}  [{} .. {}]",
+            format!("{}:{}", device, axis),
+            value,
+            config.min,
+            config.max,
+            width = width
+        )
+        .unwrap();
+        match mapper.out_of_range_count(device, *axis) {
+            0 => writeln!(out).unwrap(),
+            count => writeln!(out, "  {} out of range", count).unwrap(),
+        }
+    }
+
+    return out;
+}
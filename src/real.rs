@@ -16,27 +16,187 @@
  */
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use evdev_rs::DeviceWrapper;
-use evdev_rs::enums::{EV_ABS, EventCode};
+use evdev_rs::{DeviceWrapper, GrabMode};
+use evdev_rs::enums::{
+    EV_ABS, EV_REL, EV_SYN, EventCode, EventType, InputProp, int_to_ev_abs, int_to_ev_key,
+    int_to_ev_msc, int_to_ev_rel, int_to_ev_sw, int_to_input_prop,
+};
+use evdev_rs::util::event_code_to_int;
 use libudev::Device;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use nix::libc;
 
-use crate::config::{Axis, RealDeviceMatcher};
-use crate::listener::AxisUpdate;
-use crate::virt::VirtAxis;
+use crate::config::{
+    Axis, AxisCalibration, Pattern, RealDeviceConfig, RealDeviceMatcher, RelativeAxisConfig,
+};
+use crate::error::DeviceError;
+use crate::source::{AbsAxisInfo, DeviceIdentity, DeviceSource};
 
 pub struct RealDevice {
     pub name: String,
-    pub matcher: RealDeviceMatcher,
-    evdev_device: evdev_rs::Device,
+    pub config: RealDeviceConfig,
+    evdev_device: Option<evdev_rs::Device>,
+    accumulators: HashMap<Axis, Accumulator>,
+    /// Events read since the last `SYN_REPORT`.
+    pending_frame: Frame,
 }
 
-fn axis_to_event_code(axis: &Axis) -> EventCode {
+/// Everything a real device reported up to a `SYN_REPORT`, which is applied to the virtual devices
+/// at once.
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub axes: Vec<AxisUpdate>,
+    /// All events of the frame, for passing them through to virtual devices.
+    pub events: Vec<RawEvent>,
+}
+
+#[derive(Debug)]
+pub struct AxisUpdate {
+    pub device: String,
+    pub axis: Axis,
+    pub new_value: i32,
+}
+
+#[derive(Debug)]
+pub struct RawEvent {
+    pub device: String,
+    pub event_code: EventCode,
+    pub value: i32,
+}
+
+/// Sums up the deltas reported by a relative axis, clamped to the configured range.
+#[derive(Debug)]
+struct Accumulator {
+    config: RelativeAxisConfig,
+    value: i32,
+    pending_decay: f64,
+}
+
+impl Accumulator {
+    fn new(config: RelativeAxisConfig) -> Accumulator {
+        return Accumulator {
+            value: config.center.clamp(config.min, config.max),
+            config,
+            pending_decay: 0.0,
+        };
+    }
+
+    fn add(&mut self, delta: i32) -> i32 {
+        self.value = self
+            .value
+            .saturating_add(delta)
+            .clamp(self.config.min, self.config.max);
+        self.pending_decay = 0.0;
+        return self.value;
+    }
+
+    /// Moves the value towards the center by as much as the configured decay allows for the
+    /// given duration. Returns the new value if it changed.
+    fn decay(&mut self, elapsed: Duration) -> Option<i32> {
+        let speed = self.config.decay?;
+        let center = self.config.center.clamp(self.config.min, self.config.max);
+        if self.value == center {
+            return None;
+        }
+
+        self.pending_decay += speed as f64 * elapsed.as_secs_f64();
+        let step = self.pending_decay.floor();
+        if step < 1.0 {
+            return None;
+        }
+        self.pending_decay -= step;
+
+        let distance = (center as i64 - self.value as i64).abs();
+        let step = (step as i64).min(distance);
+        self.value = if self.value < center {
+            self.value + step as i32
+        } else {
+            self.value - step as i32
+        };
+        return Some(self.value);
+    }
+}
+
+/// Maps a raw value from the calibrated range onto the given range.
+fn apply_calibration(calibration: &AxisCalibration, value: i32, (min, max): (i32, i32)) -> i32 {
+    let (min, max) = (min as i64, max as i64);
+    let (cal_min, cal_max, value) = (calibration.min as i64, calibration.max as i64, value as i64);
+    let scale = |value: i64, (from_min, from_max): (i64, i64), (to_min, to_max): (i64, i64)| {
+        if from_max == from_min {
+            return to_min;
+        }
+        return to_min + (value - from_min) * (to_max - to_min) / (from_max - from_min);
+    };
+
+    let result = match calibration.center.map(|center| center as i64) {
+        Some(center) if cal_min < center && center < cal_max => {
+            let middle = (min + max) / 2;
+            if value < center {
+                scale(value, (cal_min, center), (min, middle))
+            } else {
+                scale(value, (center, cal_max), (middle, max))
+            }
+        }
+        _ => scale(value, (cal_min, cal_max), (min, max)),
+    };
+    return result.clamp(min.min(max), max.max(min)) as i32;
+}
+
+const ABS_MAX: u32 = 0x3f;
+const REL_MAX: u32 = 0x0f;
+const KEY_MAX: u32 = 0x2ff;
+const MSC_MAX: u32 = 0x07;
+const SW_MAX: u32 = 0x10;
+const INPUT_PROP_MAX: u32 = 0x1f;
+
+/// All input properties.
+pub fn input_properties() -> impl Iterator<Item = InputProp> {
+    return (0..INPUT_PROP_MAX).filter_map(int_to_input_prop);
+}
+
+/// All key, absolute, relative, misc and switch event codes.
+pub fn event_codes() -> impl Iterator<Item = EventCode> {
+    return (0..=KEY_MAX)
+        .filter_map(int_to_ev_key)
+        .map(EventCode::EV_KEY)
+        .chain(
+            (0..=ABS_MAX)
+                .filter_map(int_to_ev_abs)
+                .map(EventCode::EV_ABS),
+        )
+        .chain(
+            (0..=REL_MAX)
+                .filter_map(int_to_ev_rel)
+                .map(EventCode::EV_REL),
+        )
+        .chain(
+            (0..=MSC_MAX)
+                .filter_map(int_to_ev_msc)
+                .map(EventCode::EV_MSC),
+        )
+        .chain((0..=SW_MAX).filter_map(int_to_ev_sw).map(EventCode::EV_SW));
+}
+
+/// The kernel name of an event code. Unlike its `Display` implementation, this does not need
+/// libevdev, but it only knows the first of several names for the same code.
+pub fn event_code_name(event_code: &EventCode) -> String {
+    return match event_code {
+        EventCode::EV_KEY(code) => format!("{:?}", code),
+        EventCode::EV_ABS(code) => format!("{:?}", code),
+        EventCode::EV_REL(code) => format!("{:?}", code),
+        EventCode::EV_MSC(code) => format!("{:?}", code),
+        EventCode::EV_SW(code) => format!("{:?}", code),
+        other => format!("{:?}", other),
+    };
+}
+
+pub fn axis_to_event_code(axis: &Axis) -> EventCode {
     return match axis {
         Axis::X => EventCode::EV_ABS(EV_ABS::ABS_X),
         Axis::Y => EventCode::EV_ABS(EV_ABS::ABS_Y),
@@ -49,10 +209,29 @@ fn axis_to_event_code(axis: &Axis) -> EventCode {
         Axis::Wheel => EventCode::EV_ABS(EV_ABS::ABS_WHEEL),
         Axis::Gas => EventCode::EV_ABS(EV_ABS::ABS_GAS),
         Axis::Brake => EventCode::EV_ABS(EV_ABS::ABS_BRAKE),
+        Axis::RelX => EventCode::EV_REL(EV_REL::REL_X),
+        Axis::RelY => EventCode::EV_REL(EV_REL::REL_Y),
+        Axis::RelZ => EventCode::EV_REL(EV_REL::REL_Z),
+        Axis::RelRX => EventCode::EV_REL(EV_REL::REL_RX),
+        Axis::RelRY => EventCode::EV_REL(EV_REL::REL_RY),
+        Axis::RelRZ => EventCode::EV_REL(EV_REL::REL_RZ),
+        Axis::RelHWheel => EventCode::EV_REL(EV_REL::REL_HWHEEL),
+        Axis::RelDial => EventCode::EV_REL(EV_REL::REL_DIAL),
+        Axis::RelWheel => EventCode::EV_REL(EV_REL::REL_WHEEL),
+        Axis::RelMisc => EventCode::EV_REL(EV_REL::REL_MISC),
+        Axis::Hat0X => EventCode::EV_ABS(EV_ABS::ABS_HAT0X),
+        Axis::Hat0Y => EventCode::EV_ABS(EV_ABS::ABS_HAT0Y),
+        Axis::Hat1X => EventCode::EV_ABS(EV_ABS::ABS_HAT1X),
+        Axis::Hat1Y => EventCode::EV_ABS(EV_ABS::ABS_HAT1Y),
+        Axis::Hat2X => EventCode::EV_ABS(EV_ABS::ABS_HAT2X),
+        Axis::Hat2Y => EventCode::EV_ABS(EV_ABS::ABS_HAT2Y),
+        Axis::Hat3X => EventCode::EV_ABS(EV_ABS::ABS_HAT3X),
+        Axis::Hat3Y => EventCode::EV_ABS(EV_ABS::ABS_HAT3Y),
+        Axis::Key(key) => EventCode::EV_KEY(*key),
     };
 }
 
-fn event_code_to_axis(event_code: &EventCode) -> Option<Axis> {
+pub fn event_code_to_axis(event_code: &EventCode) -> Option<Axis> {
     return match event_code {
         EventCode::EV_ABS(EV_ABS::ABS_X) => Some(Axis::X),
         EventCode::EV_ABS(EV_ABS::ABS_Y) => Some(Axis::Y),
@@ -65,59 +244,306 @@ fn event_code_to_axis(event_code: &EventCode) -> Option<Axis> {
         EventCode::EV_ABS(EV_ABS::ABS_WHEEL) => Some(Axis::Wheel),
         EventCode::EV_ABS(EV_ABS::ABS_GAS) => Some(Axis::Gas),
         EventCode::EV_ABS(EV_ABS::ABS_BRAKE) => Some(Axis::Brake),
+        EventCode::EV_REL(EV_REL::REL_X) => Some(Axis::RelX),
+        EventCode::EV_REL(EV_REL::REL_Y) => Some(Axis::RelY),
+        EventCode::EV_REL(EV_REL::REL_Z) => Some(Axis::RelZ),
+        EventCode::EV_REL(EV_REL::REL_RX) => Some(Axis::RelRX),
+        EventCode::EV_REL(EV_REL::REL_RY) => Some(Axis::RelRY),
+        EventCode::EV_REL(EV_REL::REL_RZ) => Some(Axis::RelRZ),
+        EventCode::EV_REL(EV_REL::REL_HWHEEL) => Some(Axis::RelHWheel),
+        EventCode::EV_REL(EV_REL::REL_DIAL) => Some(Axis::RelDial),
+        EventCode::EV_REL(EV_REL::REL_WHEEL) => Some(Axis::RelWheel),
+        EventCode::EV_REL(EV_REL::REL_MISC) => Some(Axis::RelMisc),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT0X) => Some(Axis::Hat0X),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT0Y) => Some(Axis::Hat0Y),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT1X) => Some(Axis::Hat1X),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT1Y) => Some(Axis::Hat1Y),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT2X) => Some(Axis::Hat2X),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT2Y) => Some(Axis::Hat2Y),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT3X) => Some(Axis::Hat3X),
+        EventCode::EV_ABS(EV_ABS::ABS_HAT3Y) => Some(Axis::Hat3Y),
+        EventCode::EV_KEY(key) => Some(Axis::Key(*key)),
         _ => None,
     };
 }
 
-pub fn get_event_devices() -> HashMap<(u16, u16), PathBuf> {
-    let mut result = HashMap::new();
+/// Everything udev tells us about an event device which can be used to match it.
+#[derive(Debug, Clone, Default)]
+pub struct EventDeviceInfo {
+    pub path: PathBuf,
+    pub links: Vec<PathBuf>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub bustype: Option<u16>,
+    pub name: Option<String>,
+    pub phys: Option<String>,
+    pub uniq: Option<String>,
+    pub properties: HashMap<String, String>,
+    pub abs_capabilities: Vec<u64>,
+    pub rel_capabilities: Vec<u64>,
+    pub key_capabilities: Vec<u64>,
+}
+
+impl EventDeviceInfo {
+    fn from_udev(event_device: &Device) -> Option<EventDeviceInfo> {
+        let path = event_device.devnode()?.to_path_buf();
+        let parent = event_device.parent();
 
-    let context = libudev::Context::new().unwrap();
-    let mut enumerator = libudev::Enumerator::new(&context).unwrap();
-    enumerator.match_subsystem("input").unwrap();
-    enumerator.match_is_initialized().unwrap();
-    for event_device in enumerator.scan_devices().unwrap().filter(is_event_device) {
-        let dev_node = match event_device.devnode() {
-            Some(value) => value.to_path_buf(),
-            None => continue,
+        let property = |name: &str| -> Option<String> {
+            return event_device
+                .property_value(name)
+                .map(|value| value.to_string_lossy().into_owned());
+        };
+        let parent_attribute = |name: &str| -> Option<String> {
+            return parent
+                .as_ref()?
+                .attribute_value(name)
+                .map(|value| value.to_string_lossy().into_owned());
+        };
+        let parse_hex = |value: Option<String>| -> Option<u16> {
+            return u16::from_str_radix(value?.trim(), 16).ok();
         };
 
-        let vendor_id = match event_device.property_value("ID_VENDOR_ID") {
-            Some(value) => u16::from_str_radix(&value.to_string_lossy(), 16).unwrap(),
-            None => {
-                debug!(
-                    "Event device '{}' does not have a known vendor ID. Ignoring.",
-                    dev_node.to_string_lossy()
-                );
-                continue;
-            }
+        return Some(EventDeviceInfo {
+            links: property("DEVLINKS")
+                .map(|links| links.split_whitespace().map(PathBuf::from).collect())
+                .unwrap_or_default(),
+            vendor_id: parse_hex(property("ID_VENDOR_ID"))
+                .or_else(|| parse_hex(parent_attribute("id/vendor"))),
+            product_id: parse_hex(property("ID_MODEL_ID"))
+                .or_else(|| parse_hex(parent_attribute("id/product"))),
+            bustype: parse_hex(parent_attribute("id/bustype")),
+            name: parent_attribute("name"),
+            phys: parent_attribute("phys").filter(|phys| !phys.is_empty()),
+            uniq: parent_attribute("uniq").filter(|uniq| !uniq.is_empty()),
+            properties: event_device
+                .properties()
+                .map(|property| {
+                    (
+                        property.name().to_string_lossy().into_owned(),
+                        property.value().to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+            abs_capabilities: parse_bitmask(
+                &parent_attribute("capabilities/abs").unwrap_or_default(),
+            ),
+            rel_capabilities: parse_bitmask(
+                &parent_attribute("capabilities/rel").unwrap_or_default(),
+            ),
+            key_capabilities: parse_bitmask(
+                &parent_attribute("capabilities/key").unwrap_or_default(),
+            ),
+            path,
+        });
+    }
+
+    pub fn port_path(&self) -> Option<&String> {
+        return self.properties.get("ID_PATH");
+    }
+
+    pub fn supports(&self, axis: &Axis) -> bool {
+        let (ev_type, code) = event_code_to_int(&axis_to_event_code(axis));
+        let capabilities = if ev_type == EventType::EV_REL as u32 {
+            &self.rel_capabilities
+        } else if ev_type == EventType::EV_KEY as u32 {
+            &self.key_capabilities
+        } else {
+            &self.abs_capabilities
+        };
+        return match capabilities.get(code as usize / 64) {
+            Some(word) => word & (1 << (code % 64)) != 0,
+            None => false,
         };
+    }
 
-        let product_id = match event_device.property_value("ID_MODEL_ID") {
-            Some(value) => u16::from_str_radix(&value.to_string_lossy(), 16).unwrap(),
-            None => {
-                debug!(
-                    "Event device '{}' does not have a known product ID. Ignoring.",
-                    dev_node.to_string_lossy()
-                );
-                continue;
-            }
+    /// The names of the links in `/dev/input/by-id` pointing to the device.
+    pub fn by_id_names(&self) -> impl Iterator<Item = String> + '_ {
+        return self
+            .links
+            .iter()
+            .filter(|link| link.starts_with("/dev/input/by-id"))
+            .filter_map(|link| link.file_name())
+            .map(|name| name.to_string_lossy().into_owned());
+    }
+}
+
+pub fn get_event_devices() -> Result<Vec<EventDeviceInfo>, DeviceError> {
+    let mut result = Vec::new();
+
+    let context = libudev::Context::new().map_err(DeviceError::Udev)?;
+    let mut enumerator = libudev::Enumerator::new(&context).map_err(DeviceError::Udev)?;
+    enumerator
+        .match_subsystem("input")
+        .map_err(DeviceError::Udev)?;
+    enumerator
+        .match_is_initialized()
+        .map_err(DeviceError::Udev)?;
+    let event_devices = enumerator.scan_devices().map_err(DeviceError::Udev)?;
+    for event_device in event_devices.filter(is_event_device) {
+        let info = match EventDeviceInfo::from_udev(&event_device) {
+            Some(info) => info,
+            None => continue,
         };
 
         debug!(
-            "Found event device '{}' with IDs '{:04x}:{:04x}'.",
-            dev_node.to_string_lossy(),
-            vendor_id,
-            product_id
+            "Found event device '{}' named {:?} with IDs '{:04x}:{:04x}'.",
+            info.path.to_string_lossy(),
+            info.name.as_deref().unwrap_or(""),
+            info.vendor_id.unwrap_or(0),
+            info.product_id.unwrap_or(0)
         );
 
-        result.insert((vendor_id, product_id), dev_node);
+        result.push(info);
+    }
+
+    return Ok(result);
+}
+
+impl RealDeviceMatcher {
+    pub fn matches(&self, device: &EventDeviceInfo) -> bool {
+        if let Some(path) = &self.path {
+            if device.path != *path && !device.links.contains(path) {
+                return false;
+            }
+        }
+        if self.vendor_id.is_some() && device.vendor_id != self.vendor_id {
+            return false;
+        }
+        if self.product_id.is_some() && device.product_id != self.product_id {
+            return false;
+        }
+        if self.name.is_some() && device.name != self.name {
+            return false;
+        }
+        if let Some(Pattern(regex)) = &self.name_regex {
+            if !device
+                .name
+                .as_ref()
+                .is_some_and(|name| regex.is_match(name))
+            {
+                return false;
+            }
+        }
+        if self.phys.is_some() && device.phys != self.phys {
+            return false;
+        }
+        if self.uniq.is_some() && device.uniq != self.uniq {
+            return false;
+        }
+        if let Some(bustype) = &self.bustype {
+            if device.bustype != Some(bustype.code()) {
+                return false;
+            }
+        }
+        for (key, value) in &self.udev_properties {
+            if device.properties.get(key) != Some(value) {
+                return false;
+            }
+        }
+        if let Some(Pattern(regex)) = &self.by_id {
+            if !device.by_id_names().any(|name| regex.is_match(&name)) {
+                return false;
+            }
+        }
+        if self.port_path.is_some() && device.port_path() != self.port_path.as_ref() {
+            return false;
+        }
+        if !self.has_axes.iter().all(|axis| device.supports(axis)) {
+            return false;
+        }
+        return true;
+    }
+
+    /// All matching devices, in the order used for `index`.
+    pub fn candidates<'a>(
+        &self,
+        available_devices: &'a [EventDeviceInfo],
+    ) -> Vec<&'a EventDeviceInfo> {
+        let mut candidates: Vec<&EventDeviceInfo> = available_devices
+            .iter()
+            .filter(|device| self.matches(device))
+            .collect();
+        candidates.sort_by(|a, b| (a.port_path(), &a.path).cmp(&(b.port_path(), &b.path)));
+        return candidates;
+    }
+
+    /// Picks the device to use from all available devices.
+    pub fn select<'a>(
+        &self,
+        available_devices: &'a [EventDeviceInfo],
+    ) -> Result<&'a EventDeviceInfo, String> {
+        let candidates = self.candidates(available_devices);
+
+        if let Some(index) = self.index {
+            return match candidates.get(index) {
+                Some(device) => Ok(device),
+                None => Err(format!(
+                    "Only {} devices match {}:{}",
+                    candidates.len(),
+                    self,
+                    describe_candidates(&candidates)
+                )),
+            };
+        }
+
+        return match candidates.as_slice() {
+            [] => Err(format!("No device matching {} was found", self)),
+            [device] => Ok(device),
+            _ => Err(format!(
+                "{} devices match {}, add more criteria or an index to pick one:{}",
+                candidates.len(),
+                self,
+                describe_candidates(&candidates)
+            )),
+        };
     }
+}
+
+fn describe_candidates(candidates: &[&EventDeviceInfo]) -> String {
+    return candidates
+        .iter()
+        .enumerate()
+        .map(|(index, device)| {
+            format!(
+                "\n  [{}] '{}': name {:?}, IDs {:04x}:{:04x}, port path {:?}, uniq {:?}",
+                index,
+                device.path.to_string_lossy(),
+                device.name.as_deref().unwrap_or(""),
+                device.vendor_id.unwrap_or(0),
+                device.product_id.unwrap_or(0),
+                device.port_path().map(String::as_str).unwrap_or(""),
+                device.uniq.as_deref().unwrap_or("")
+            )
+        })
+        .collect();
+}
+
+/// Parses a capability bitmask as exposed by the kernel in sysfs: hexadecimal words separated by
+/// spaces, most significant first. The result holds the least significant word first.
+fn parse_bitmask(mask: &str) -> Vec<u64> {
+    return mask
+        .split_whitespace()
+        .rev()
+        .map(|word| u64::from_str_radix(word, 16).unwrap_or(0))
+        .collect();
+}
 
-    return result;
+/// Opens an event device without blocking on reads, such that it can be polled along with others.
+pub fn open_event_device(path: &Path) -> Result<evdev_rs::Device, DeviceError> {
+    let open_error = |source| DeviceError::Open {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .map_err(open_error)?;
+    return evdev_rs::Device::new_from_file(file).map_err(open_error);
 }
 
-fn is_event_device(device: &Device) -> bool {
+pub fn is_event_device(device: &Device) -> bool {
     return match device.sysname() {
         Some(name) => name.to_string_lossy().starts_with("event"),
         None => false,
@@ -125,94 +551,332 @@ fn is_event_device(device: &Device) -> bool {
 }
 
 impl RealDevice {
-    pub fn new(
-        name: String,
-        matcher: &RealDeviceMatcher,
-        paths_by_ids: &HashMap<(u16, u16), PathBuf>,
-    ) -> Result<RealDevice, String> {
-        let path = match matcher {
-            RealDeviceMatcher::Path(path) => path.clone(),
-            RealDeviceMatcher::VendorAndProduct {
-                vendor_id,
-                product_id,
-            } => match paths_by_ids.get(&(*vendor_id, *product_id)) {
-                Some(path) => path.clone(),
-                None => {
-                    return Err(format!(
-                        "No device with IDs '{:04x}:{:04x}' was found",
-                        vendor_id, product_id
-                    ));
-                }
-            },
+    /// Creates a real device which is not connected yet.
+    pub fn new(name: String, config: &RealDeviceConfig) -> RealDevice {
+        return RealDevice {
+            name,
+            config: config.clone(),
+            evdev_device: None,
+            accumulators: HashMap::new(),
+            pending_frame: Frame::default(),
+        };
+    }
+
+    fn evdev_device(&self) -> Result<&evdev_rs::Device, DeviceError> {
+        return self
+            .evdev_device
+            .as_ref()
+            .ok_or_else(|| DeviceError::NotConnected {
+                device: self.name.clone(),
+            });
+    }
+
+    /// Applies the configured calibration of an absolute axis, if any, to a raw value.
+    fn calibrate(&self, axis: &Axis, value: i32) -> i32 {
+        let calibration = match self.config.calibration.get(axis) {
+            Some(calibration) => calibration,
+            None => return value,
+        };
+        return match self.range(axis) {
+            Some(range) => apply_calibration(calibration, value, range),
+            None => value,
+        };
+    }
+
+    fn relative_axis_config(&self, axis: &Axis) -> RelativeAxisConfig {
+        return self
+            .config
+            .relative_axes
+            .get(axis)
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    fn accumulator(&mut self, axis: &Axis) -> &mut Accumulator {
+        let config = self.relative_axis_config(axis);
+        return self
+            .accumulators
+            .entry(*axis)
+            .or_insert_with(|| Accumulator::new(config));
+    }
+}
+
+impl DeviceSource for RealDevice {
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// Finds the event device described by the config among the available devices and opens it.
+    fn connect(&mut self, available_devices: &[EventDeviceInfo]) -> Result<(), DeviceError> {
+        let matcher = &self.config.matcher;
+        let not_found = |message| DeviceError::NotFound {
+            device: self.name.clone(),
+            message,
+        };
+        let path = match &matcher.path {
+            Some(path) if matcher.is_path_only() => path.clone(),
+            _ if matcher.is_empty() => return Err(not_found("No criteria given".to_owned())),
+            _ => matcher
+                .select(available_devices)
+                .map_err(not_found)?
+                .path
+                .clone(),
         };
 
         debug!(
-            "Using path '{}' for matcher {:?}",
+            "Using path '{}' for matcher {}",
             path.to_string_lossy(),
             matcher
         );
 
-        let file = File::open(&path).map_err(|err| err.to_string())?;
-        let evdev_device = evdev_rs::Device::new_from_file(file).unwrap();
+        let mut evdev_device = open_event_device(&path)?;
 
-        info!("Opened event device '{}'", path.to_string_lossy());
+        if self.config.grab {
+            evdev_device
+                .grab(GrabMode::Grab)
+                .map_err(|source| DeviceError::Grab {
+                    path: path.clone(),
+                    source,
+                })?;
+            debug!("Grabbed event device '{}'", path.to_string_lossy());
+        }
 
-        return Ok(RealDevice {
-            name,
-            matcher: matcher.clone(),
-            evdev_device,
+        info!(
+            "Opened event device '{}' for device {}",
+            path.to_string_lossy(),
+            self.name
+        );
+
+        self.evdev_device = Some(evdev_device);
+        return Ok(());
+    }
+
+    fn disconnect(&mut self) {
+        self.pending_frame = Frame::default();
+        if let Some(mut evdev_device) = self.evdev_device.take() {
+            if self.config.grab {
+                // Fails if the device is already gone, in which case the grab went with it.
+                let _ = evdev_device.grab(GrabMode::Ungrab);
+            }
+            info!("Device {} was disconnected", self.name);
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        return self.evdev_device.is_some();
+    }
+
+    /// The file descriptor to poll for events while the device is connected.
+    fn raw_fd(&self) -> Option<RawFd> {
+        return Some(self.evdev_device.as_ref()?.file().as_raw_fd());
+    }
+
+    fn identity(&self) -> Option<DeviceIdentity> {
+        let device = self.evdev_device.as_ref()?;
+        return Some(DeviceIdentity {
+            name: device.name().unwrap_or_default().to_owned(),
+            vendor_id: device.vendor_id(),
+            product_id: device.product_id(),
+            bustype: device.bustype(),
+            version: device.version(),
+            phys: device.phys().map(str::to_owned),
+            properties: input_properties()
+                .filter(|property| device.has_property(property))
+                .collect(),
         });
     }
 
-    pub fn read(&self, axis: &Axis) -> Result<i32, String> {
-        return match self.evdev_device.abs_info(&axis_to_event_code(axis)) {
-            Some(info) => Ok(info.value),
-            None => Err(format!(
-                "Device {:?} does not support axis {:?}",
-                self.matcher, axis
-            )),
+    fn supported_codes(&self) -> Vec<EventCode> {
+        return match &self.evdev_device {
+            Some(device) => event_codes()
+                .filter(|code| device.has_event_code(code))
+                .collect(),
+            None => Vec::new(),
+        };
+    }
+
+    fn abs_info(&self, event_code: &EventCode) -> Option<AbsAxisInfo> {
+        let info = self.evdev_device.as_ref()?.abs_info(event_code)?;
+        return Some(AbsAxisInfo {
+            value: info.value,
+            minimum: info.minimum,
+            maximum: info.maximum,
+            fuzz: info.fuzz,
+            flat: info.flat,
+            resolution: info.resolution,
+        });
+    }
+
+    fn read(&self, axis: &Axis) -> Result<i32, DeviceError> {
+        let unsupported = || DeviceError::Unsupported {
+            device: self.name.clone(),
+            axis: *axis,
+        };
+        if axis.is_relative() {
+            if !self
+                .evdev_device()?
+                .has_event_code(&axis_to_event_code(axis))
+            {
+                return Err(unsupported());
+            }
+            return Ok(match self.accumulators.get(axis) {
+                Some(accumulator) => accumulator.value,
+                None => Accumulator::new(self.relative_axis_config(axis)).value,
+            });
+        }
+
+        if axis.is_key() {
+            return self
+                .evdev_device()?
+                .event_value(&axis_to_event_code(axis))
+                .ok_or_else(unsupported);
+        }
+
+        return match self.evdev_device()?.abs_info(&axis_to_event_code(axis)) {
+            Some(info) => Ok(self.calibrate(axis, info.value)),
+            None => Err(unsupported()),
         };
     }
 
-    pub fn next_event(&self) -> Option<AxisUpdate> {
+    /// The range of values the axis can take, as reported by the device or configured for
+    /// relative axes.
+    fn range(&self, axis: &Axis) -> Option<(i32, i32)> {
+        if axis.is_key() {
+            return Some((0, 1));
+        }
+        if axis.is_relative() {
+            let config = self
+                .config
+                .relative_axes
+                .get(axis)
+                .cloned()
+                .unwrap_or_default();
+            return Some((config.min, config.max));
+        }
+
+        let info = self
+            .evdev_device
+            .as_ref()?
+            .abs_info(&axis_to_event_code(axis))?;
+        return Some((info.minimum, info.maximum));
+    }
+
+    /// Whether any of the configured relative axes return to their center over time.
+    fn has_decay(&self) -> bool {
+        return self
+            .config
+            .relative_axes
+            .values()
+            .any(|config| config.decay.is_some());
+    }
+
+    /// Applies the configured decay of all relative axes for the given duration.
+    fn decay(&mut self, elapsed: Duration) -> Vec<AxisUpdate> {
+        let mut updates = Vec::new();
+        let axes: Vec<Axis> = self
+            .config
+            .relative_axes
+            .iter()
+            .filter(|(_, config)| config.decay.is_some())
+            .map(|(axis, _)| *axis)
+            .collect();
+        for axis in axes {
+            if let Some(new_value) = self.accumulator(&axis).decay(elapsed) {
+                updates.push(AxisUpdate {
+                    device: self.name.clone(),
+                    axis,
+                    new_value,
+                });
+            }
+        }
+        return updates;
+    }
+
+    /// Reads all events which are available without blocking and returns the frames completed by
+    /// a `SYN_REPORT`, along with the resulting axis updates. Events of an incomplete frame are
+    /// kept until the next call. Returns `None` once the device is gone.
+    fn read_frames(&mut self) -> Option<Vec<Frame>> {
+        let mut frames = Vec::new();
         let mut read_flag = evdev_rs::ReadFlag::NORMAL;
         loop {
-            match self.evdev_device.next_event(read_flag) {
-                Ok((evdev_rs::ReadStatus::Success, event)) => {
-                    return Some(AxisUpdate {
-                        device: self.name.clone(),
-                        axis: match event_code_to_axis(&event.event_code) {
-                            Some(axis) => axis,
-                            None => {
-                                trace!("Unhandled event code: {}", event.event_code);
-                                continue;
-                            }
-                        },
-                        new_value: event.value,
-                    });
+            let result = self.evdev_device.as_ref()?.next_event(read_flag);
+            let event = match result {
+                Ok((evdev_rs::ReadStatus::Success, event)) => event,
+                // Events which were dropped are replayed in sync mode, ending with a `SYN_REPORT`.
+                // The incomplete frame before the `SYN_DROPPED` is discarded, as the replayed
+                // state replaces it.
+                Ok((evdev_rs::ReadStatus::Sync, event)) => {
+                    if read_flag == evdev_rs::ReadFlag::NORMAL {
+                        self.pending_frame = Frame::default();
+                    }
+                    read_flag = evdev_rs::ReadFlag::SYNC;
+                    event
                 }
-                Ok((evdev_rs::ReadStatus::Sync, _)) => read_flag = evdev_rs::ReadFlag::SYNC,
                 Err(err) => match err.raw_os_error() {
-                    Some(libc::EAGAIN) => read_flag = evdev_rs::ReadFlag::NORMAL,
-                    Some(_) | None => panic!("Unable to get next event: {}", err),
+                    Some(libc::EAGAIN) if read_flag == evdev_rs::ReadFlag::SYNC => {
+                        read_flag = evdev_rs::ReadFlag::NORMAL;
+                        continue;
+                    }
+                    Some(libc::EAGAIN) => return Some(frames),
+                    Some(_) | None => {
+                        warn!(
+                            "Unable to get next event from device {}: {}",
+                            self.name, err
+                        );
+                        return None;
+                    }
                 },
+            };
+
+            match event.event_code {
+                EventCode::EV_SYN(EV_SYN::SYN_REPORT) => {
+                    frames.push(std::mem::take(&mut self.pending_frame));
+                    continue;
+                }
+                EventCode::EV_SYN(_) => continue,
+                _ => {}
+            }
+
+            match event_code_to_axis(&event.event_code) {
+                Some(axis) => {
+                    let new_value = if axis.is_relative() {
+                        self.accumulator(&axis).add(event.value)
+                    } else {
+                        self.calibrate(&axis, event.value)
+                    };
+                    self.pending_frame.axes.push(AxisUpdate {
+                        device: self.name.clone(),
+                        axis,
+                        new_value,
+                    });
+                }
+                None => trace!("Event code without axis: {}", event.event_code),
             }
+            self.pending_frame.events.push(RawEvent {
+                device: self.name.clone(),
+                event_code: event.event_code,
+                value: event.value,
+            });
         }
     }
 
-    pub fn supports(&self, axis: &Axis) -> bool {
-        return self.evdev_device.has_event_code(&axis_to_event_code(axis));
+    fn supports(&self, axis: &Axis) -> bool {
+        return match &self.evdev_device {
+            Some(device) => device.has_event_code(&axis_to_event_code(axis)),
+            None => false,
+        };
     }
 }
 
 pub struct RealAxis {
-    pub device: Arc<RwLock<RealDevice>>,
+    pub device: String,
     pub axis: Axis,
-    pub downstream: Vec<VirtAxis>,
+    /// The virtual axes whose expressions depend on this axis.
+    pub downstream: Vec<(String, Axis)>,
 }
 
 impl RealAxis {
-    pub fn new(device: Arc<RwLock<RealDevice>>, axis: Axis) -> RealAxis {
+    pub fn new(device: String, axis: Axis) -> RealAxis {
         return RealAxis {
             device,
             axis,
@@ -221,6 +885,121 @@ impl RealAxis {
     }
 }
 
-unsafe impl Send for RealDevice {}
+impl Drop for RealDevice {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use regex::Regex;
+
+    use crate::config::{
+        Axis, AxisCalibration, BusType, Pattern, RealDeviceMatcher, RelativeAxisConfig,
+    };
+    use crate::real::{Accumulator, apply_calibration, EventDeviceInfo, parse_bitmask};
+
+    #[test]
+    fn accumulator_clamps_and_decays() {
+        let mut acc = Accumulator::new(RelativeAxisConfig {
+            min: -100,
+            max: 100,
+            center: 0,
+            decay: Some(50),
+        });
+
+        assert_eq!(acc.add(70), 70);
+        assert_eq!(acc.add(70), 100);
+        assert_eq!(acc.decay(Duration::from_millis(500)), Some(75));
+        assert_eq!(acc.decay(Duration::from_secs(10)), Some(0));
+        assert_eq!(acc.decay(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn calibration_stretches_to_declared_range() {
+        let mut calibration = AxisCalibration {
+            min: 100,
+            center: None,
+            max: 900,
+        };
+        assert_eq!(apply_calibration(&calibration, 100, (0, 1023)), 0);
+        assert_eq!(apply_calibration(&calibration, 900, (0, 1023)), 1023);
+        assert_eq!(apply_calibration(&calibration, 950, (0, 1023)), 1023);
+
+        calibration.center = Some(300);
+        assert_eq!(apply_calibration(&calibration, 300, (-512, 511)), 0);
+        assert_eq!(apply_calibration(&calibration, 200, (-512, 511)), -256);
+        assert_eq!(apply_calibration(&calibration, 600, (-512, 511)), 255);
+    }
+
+    #[test]
+    fn matcher_requires_all_criteria() {
+        let device = EventDeviceInfo {
+            path: "/dev/input/event3".into(),
+            links: vec!["/dev/input/by-id/usb-Generic_Pedals_1234-event-joystick".into()],
+            vendor_id: Some(0x0079),
+            product_id: Some(0x0006),
+            bustype: Some(0x03),
+            name: Some("Generic USB Joystick".to_owned()),
+            uniq: Some("1234".to_owned()),
+            ..EventDeviceInfo::default()
+        };
+
+        let mut matcher = RealDeviceMatcher {
+            vendor_id: Some(0x0079),
+            name_regex: Some(Pattern(Regex::new("Joystick$").unwrap())),
+            bustype: Some(BusType::Usb),
+            by_id: Some(Pattern(Regex::new("Pedals").unwrap())),
+            ..RealDeviceMatcher::default()
+        };
+        assert!(matcher.matches(&device));
+
+        matcher.uniq = Some("5678".to_owned());
+        assert!(!matcher.matches(&device));
+    }
+
+    #[test]
+    fn matcher_selects_among_identical_devices() {
+        let device = |path: &str, port: &str, abs: &str| EventDeviceInfo {
+            path: path.into(),
+            vendor_id: Some(0x044f),
+            product_id: Some(0xb10a),
+            properties: vec![("ID_PATH".to_owned(), port.to_owned())]
+                .into_iter()
+                .collect(),
+            abs_capabilities: parse_bitmask(abs),
+            ..EventDeviceInfo::default()
+        };
+        let devices = vec![
+            device("/dev/input/event7", "pci-0000:00:14.0-usb-0:2:1.0", "30063"),
+            device("/dev/input/event4", "pci-0000:00:14.0-usb-0:1:1.0", "30063"),
+            device("/dev/input/event5", "pci-0000:00:14.0-usb-0:1:1.0", "0"),
+        ];
+
+        let mut matcher = RealDeviceMatcher {
+            vendor_id: Some(0x044f),
+            ..RealDeviceMatcher::default()
+        };
+        assert!(matcher.select(&devices).is_err());
+
+        matcher.has_axes = vec![Axis::X];
+        assert!(matcher.select(&devices).is_err());
+
+        matcher.index = Some(1);
+        assert_eq!(
+            matcher.select(&devices).unwrap().path,
+            PathBuf::from("/dev/input/event7")
+        );
 
-unsafe impl Sync for RealDevice {}
+        matcher.index = None;
+        matcher.port_path = Some("pci-0000:00:14.0-usb-0:1:1.0".to_owned());
+        assert_eq!(
+            matcher.select(&devices).unwrap().path,
+            PathBuf::from("/dev/input/event4")
+        );
+    }
+}
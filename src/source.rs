@@ -0,0 +1,104 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use evdev_rs::enums::{EventCode, InputProp};
+
+use crate::config::Axis;
+use crate::error::DeviceError;
+use crate::real::{AxisUpdate, EventDeviceInfo, Frame};
+
+/// The identity of a real device, which virtual devices cloning it take over.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeviceIdentity {
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bustype: u16,
+    pub version: u16,
+    pub phys: Option<String>,
+    pub properties: Vec<InputProp>,
+}
+
+/// The range and current value of an absolute axis, as reported by a real device.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AbsAxisInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+/// Where the mapper gets the values of real axes from. `RealDevice` reads an event device, other
+/// implementations may provide values from elsewhere, e.g. fixed ones for testing a config.
+pub trait DeviceSource {
+    /// The name of the real device in the config.
+    fn name(&self) -> &str;
+
+    /// Opens the device, possibly choosing one of the available event devices.
+    fn connect(&mut self, available_devices: &[EventDeviceInfo]) -> Result<(), DeviceError>;
+
+    fn disconnect(&mut self);
+
+    fn is_connected(&self) -> bool;
+
+    /// The file descriptor to poll for frames while the device is connected. Sources without one
+    /// are fed through `Mapper::push_frame` instead.
+    fn raw_fd(&self) -> Option<RawFd>;
+
+    /// Reads all frames which are available without blocking. Returns `None` once the device is
+    /// gone.
+    fn read_frames(&mut self) -> Option<Vec<Frame>>;
+
+    /// The current value of an axis.
+    fn read(&self, axis: &Axis) -> Result<i32, DeviceError>;
+
+    /// The range of values the axis can take, if known.
+    fn range(&self, axis: &Axis) -> Option<(i32, i32)>;
+
+    fn supports(&self, axis: &Axis) -> bool;
+
+    /// Whether `decay` needs to be called regularly.
+    fn has_decay(&self) -> bool {
+        return false;
+    }
+
+    /// Lets the values of the axes change with time, returning the updates.
+    fn decay(&mut self, _elapsed: Duration) -> Vec<AxisUpdate> {
+        return Vec::new();
+    }
+
+    /// The identity of the device while it is connected, for virtual devices cloning it.
+    fn identity(&self) -> Option<DeviceIdentity> {
+        return None;
+    }
+
+    /// All event codes the device supports while it is connected, for virtual devices passing it
+    /// through or cloning it.
+    fn supported_codes(&self) -> Vec<EventCode> {
+        return Vec::new();
+    }
+
+    /// The range of an absolute axis, given by its event code, if the device supports it.
+    fn abs_info(&self, _event_code: &EventCode) -> Option<AbsAxisInfo> {
+        return None;
+    }
+}
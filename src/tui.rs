@@ -0,0 +1,579 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::error;
+use nix::{libc, unistd};
+use nix::sys::termios;
+use nix::sys::termios::{SetArg, Termios};
+
+use pimp_my_axis::{AxisExpression, Mapper};
+use pimp_my_axis::config::{Axis, parse_config, update_config_file};
+use pimp_my_axis::error::{Error, RuntimeError};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+const BAR_WIDTH: usize = 30;
+const CURVE_HEIGHT: usize = 12;
+const HELP: &str = "up/down: axis  left/right: number  +/-: adjust by 1  ]/[: adjust by 10  \
+                    tab: input  r: revert  w: write config  q: quit";
+
+nix::ioctl_read_bad!(get_window_size, libc::TIOCGWINSZ, libc::winsize);
+
+#[derive(Debug)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Tab,
+    Char(char),
+}
+
+/// Keeps the terminal in raw mode on the alternate screen until dropped.
+struct RawTerminal {
+    original: Termios,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<RawTerminal, RuntimeError> {
+        let original = termios::tcgetattr(libc::STDIN_FILENO).map_err(|err| {
+            RuntimeError::Terminal(format!("Standard input is not a terminal: {}", err))
+        })?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &raw)
+            .map_err(|err| RuntimeError::Terminal(err.to_string()))?;
+
+        print!("\x1b[?1049h\x1b[?25l");
+        return Ok(RawTerminal { original });
+    }
+
+    /// The number of columns and rows of the terminal.
+    fn size() -> (usize, usize) {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        return match unsafe { get_window_size(libc::STDOUT_FILENO, &mut size) } {
+            Ok(_) if size.ws_col > 0 && size.ws_row > 0 => {
+                (size.ws_col as usize, size.ws_row as usize)
+            }
+            _ => (80, 24),
+        };
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = termios::tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &self.original);
+    }
+}
+
+/// Translates the bytes read from the terminal into keys.
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut bytes = input.iter().copied();
+    while let Some(byte) = bytes.next() {
+        let key = match byte {
+            b'\t' => Key::Tab,
+            // Ctrl+C does not send a signal in raw mode.
+            3 => Key::Char('q'),
+            0x1b => match (bytes.next(), bytes.next()) {
+                (Some(b'['), Some(b'A')) => Key::Up,
+                (Some(b'['), Some(b'B')) => Key::Down,
+                (Some(b'['), Some(b'C')) => Key::Right,
+                (Some(b'['), Some(b'D')) => Key::Left,
+                _ => continue,
+            },
+            byte => Key::Char(byte as char),
+        };
+        keys.push(key);
+    }
+    return keys;
+}
+
+/// Runs the mapping while showing all axes in the terminal and allowing the numbers in the
+/// expressions of the virtual axes to be adjusted. Adjusted expressions are written back to the
+/// config file on request. Unless `output` is set, no uinput devices are created and no real
+/// devices are grabbed.
+pub fn tune(config_path: &Path, output: bool) -> Result<(), Error> {
+    let mut config = parse_config(config_path)?;
+    if !output {
+        for real_device in config.real_devices.values_mut() {
+            real_device.grab = false;
+        }
+    }
+
+    let mut mapper = Mapper::new(&config, output)?;
+    if let Err(err) = mapper.handle_signals() {
+        error!(
+            "Unable to handle signals, stopping is not graceful: {}",
+            err
+        );
+    }
+    mapper.start();
+    let terminal = mapper
+        .watch(libc::STDIN_FILENO)
+        .and_then(|()| RawTerminal::enter());
+    let _terminal = match terminal {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            mapper.shutdown();
+            return Err(err.into());
+        }
+    };
+
+    let mut tuner = Tuner::new(config_path, mapper);
+    let mut last_draw: Option<Instant> = None;
+    let mut buffer = [0u8; 64];
+    let mut result = Ok(());
+    'running: while !tuner.mapper.is_stopping() {
+        let ready = match tuner.mapper.process(Some(REFRESH_INTERVAL)) {
+            Ok(ready) => ready,
+            Err(err) => {
+                result = Err(err.into());
+                break;
+            }
+        };
+
+        if ready.contains(&libc::STDIN_FILENO) {
+            let count = unistd::read(libc::STDIN_FILENO, &mut buffer).unwrap_or(0);
+            for key in parse_keys(&buffer[..count]) {
+                if !tuner.handle_key(key) {
+                    break 'running;
+                }
+            }
+            // Redraws right away so the tuning feels responsive.
+            last_draw = None;
+        }
+
+        if last_draw.is_none_or(|time| time.elapsed() >= REFRESH_INTERVAL) {
+            let (width, height) = RawTerminal::size();
+            print!("{}", tuner.render(width, height));
+            let _ = io::stdout().flush();
+            last_draw = Some(Instant::now());
+        }
+    }
+    tuner.mapper.shutdown();
+    return result;
+}
+
+struct Tuner {
+    config_path: PathBuf,
+    mapper: Mapper,
+    /// All virtual axes in the order they are shown in.
+    axes: Vec<(String, Axis)>,
+    /// The expressions as they are in the config file.
+    saved: HashMap<(String, Axis), AxisExpression>,
+    selected: usize,
+    literal: usize,
+    dependency: usize,
+    status: String,
+}
+
+impl Tuner {
+    fn new(config_path: &Path, mapper: Mapper) -> Tuner {
+        // The expressions of hats are derived from their config and cannot be tuned directly.
+        let mut axes: Vec<(String, Axis)> = mapper
+            .virt_axes()
+            .filter(|(device, axis)| {
+                let config = mapper.virt_device_config(device).unwrap();
+                return config.axes.contains_key(axis)
+                    || config.buttons.contains_key(axis)
+                    || config.mouse.contains_key(axis)
+                    || config.pulses.contains_key(axis);
+            })
+            .cloned()
+            .collect();
+        axes.sort_by_key(|(device, axis)| (device.clone(), axis.to_string()));
+        let saved = mapper
+            .virt_axes()
+            .map(|key| {
+                let config = mapper.virt_axis_config(&key.0, key.1).unwrap();
+                return (key.clone(), config.expr.clone());
+            })
+            .collect();
+
+        return Tuner {
+            config_path: config_path.to_path_buf(),
+            mapper,
+            axes,
+            saved,
+            selected: 0,
+            literal: 0,
+            dependency: 0,
+            status: String::new(),
+        };
+    }
+
+    fn expr(&self) -> Option<&AxisExpression> {
+        let (device, axis) = self.axes.get(self.selected)?;
+        return Some(&self.mapper.virt_axis_config(device, *axis)?.expr);
+    }
+
+    /// Returns false once the user wants to quit.
+    fn handle_key(&mut self, key: Key) -> bool {
+        let literal_count = self
+            .expr()
+            .map_or(0, |expr| expr.clone().literals_mut().len());
+        match key {
+            Key::Up if self.selected > 0 => self.select(self.selected - 1),
+            Key::Down if self.selected + 1 < self.axes.len() => self.select(self.selected + 1),
+            Key::Left if self.literal > 0 => self.literal -= 1,
+            Key::Right if self.literal + 1 < literal_count => self.literal += 1,
+            Key::Tab => self.dependency += 1,
+            Key::Char('+') | Key::Char('=') => self.adjust(1),
+            Key::Char('-') => self.adjust(-1),
+            Key::Char(']') => self.adjust(10),
+            Key::Char('[') => self.adjust(-10),
+            Key::Char('r') => self.revert(),
+            Key::Char('w') => self.write(),
+            Key::Char('q') => return false,
+            _ => {}
+        }
+        return true;
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        self.literal = 0;
+        self.dependency = 0;
+    }
+
+    fn set_expression(&mut self, expr: AxisExpression) {
+        let (device, axis) = self.axes[self.selected].clone();
+        self.status = match self.mapper.set_expression(&device, axis, expr) {
+            Ok(()) => String::new(),
+            Err(err) => err.to_string(),
+        };
+    }
+
+    fn adjust(&mut self, delta: i32) {
+        let mut expr = match self.expr() {
+            Some(expr) => expr.clone(),
+            None => return,
+        };
+        match expr.literals_mut().get_mut(self.literal) {
+            Some(literal) => **literal = literal.saturating_add(delta),
+            None => {
+                self.status = "The expression contains no numbers to adjust".to_owned();
+                return;
+            }
+        }
+        self.set_expression(expr);
+    }
+
+    fn revert(&mut self) {
+        if let Some(key) = self.axes.get(self.selected) {
+            let expr = self.saved[key].clone();
+            self.set_expression(expr);
+        }
+    }
+
+    fn write(&mut self) {
+        let mut written = 0;
+        for key in &self.axes {
+            let (device, axis) = key;
+            let expr = &self.mapper.virt_axis_config(device, *axis).unwrap().expr;
+            if *expr == self.saved[key] {
+                continue;
+            }
+
+            let axis = axis.to_string();
+            let config = self.mapper.virt_device_config(device).unwrap();
+            let keys: &[&str] = if config.buttons.contains_key(&key.1) {
+                &["virt_devices", device, "buttons", &axis]
+            } else if config.pulses.contains_key(&key.1) {
+                &["virt_devices", device, "pulses", &axis, "expr"]
+            } else if config.mouse.contains_key(&key.1) {
+                &["virt_devices", device, "mouse", &axis, "expr"]
+            } else {
+                &["virt_devices", device, "axes", &axis, "expr"]
+            };
+            // Debug formatting quotes and escapes the same way YAML does.
+            let value = format!("{:?}", expr.to_string());
+            if let Err(err) = update_config_file(&self.config_path, keys, &value) {
+                self.status = format!("Unable to write config file: {}", err);
+                return;
+            }
+            self.saved.insert(key.clone(), expr.clone());
+            written += 1;
+        }
+
+        self.status = format!(
+            "Wrote {} expression(s) to {}",
+            written,
+            self.config_path.to_string_lossy()
+        );
+    }
+
+    fn render(&self, width: usize, height: usize) -> String {
+        let mut lines = Vec::new();
+
+        let mut real_axes: Vec<&(String, Axis)> = self.mapper.real_axes().collect();
+        real_axes.sort_by_key(|(device, axis)| (device.clone(), axis.to_string()));
+        let name_width = real_axes
+            .iter()
+            .copied()
+            .chain(&self.axes)
+            .map(|(device, axis)| device.len() + axis.to_string().len() + 1)
+            .max()
+            .unwrap_or(0);
+
+        lines.push("Real axes".to_owned());
+        for (device, axis) in real_axes {
+            let device = self.mapper.real_device(device).unwrap();
+            let value = device.read(axis);
+            lines.push(format!(
+                "   {:<width$}  {:>8}  {}",
+                format!("{}:{}", device.name(), axis),
+                value
+                    .as_ref()
+                    .map_or_else(ToString::to_string, i32::to_string),
+                bar(value.ok(), device.range(axis)),
+                width = name_width
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("Virtual axes".to_owned());
+        for (index, key) in self.axes.iter().enumerate() {
+            let (device, axis) = key;
+            let config = self.mapper.virt_axis_config(device, *axis).unwrap();
+            let value = self.mapper.virt_value(device, *axis);
+            lines.push(format!(
+                " {} {:<width$}  {:>8}  {}{}",
+                if index == self.selected { ">" } else { " " },
+                format!("{}:{}", device, axis),
+                value.map_or_else(|| "-".to_owned(), |value| value.to_string()),
+                bar(value, Some((config.min, config.max))),
+                if config.expr == self.saved[key] {
+                    ""
+                } else {
+                    "  (modified)"
+                },
+                width = name_width
+            ));
+        }
+
+        if let Some(key) = self.axes.get(self.selected) {
+            lines.push(String::new());
+            lines.append(&mut self.render_selected(key, width));
+        }
+
+        lines.truncate(height.saturating_sub(2));
+        while lines.len() < height.saturating_sub(2) {
+            lines.push(String::new());
+        }
+        lines.push(self.status.clone());
+        lines.push(HELP.chars().take(width).collect());
+
+        // Raw mode does not translate line feeds, so the carriage return is needed as well.
+        return format!("\x1b[H\x1b[2J{}", lines.join("\r\n"));
+    }
+
+    fn render_selected(&self, key: &(String, Axis), width: usize) -> Vec<String> {
+        let (device, axis) = key;
+        let config = self.mapper.virt_axis_config(device, *axis).unwrap();
+        let expr = &config.expr;
+        let mut lines = vec![format!("Expression: {}", expr)];
+
+        let literals: Vec<String> = expr
+            .clone()
+            .literals_mut()
+            .iter()
+            .enumerate()
+            .map(|(index, literal)| {
+                if index == self.literal {
+                    format!("\x1b[7m{}\x1b[0m", literal)
+                } else {
+                    literal.to_string()
+                }
+            })
+            .collect();
+        lines.push(format!("Numbers:    {}", literals.join("  ")));
+
+        let dependencies = expr.dependencies();
+        if dependencies.is_empty() {
+            return lines;
+        }
+        let input = &dependencies[self.dependency % dependencies.len()];
+
+        // All other inputs are held at their current value.
+        let mut values = HashMap::new();
+        for (device, axis) in &dependencies {
+            let device = self.mapper.real_device(device).unwrap();
+            let value = match device.read(axis) {
+                Ok(value) => value,
+                Err(_) => device
+                    .range(axis)
+                    .map_or(0, |(min, max)| ((min as i64 + max as i64) / 2) as i32),
+            };
+            values.insert((device.name().to_owned(), *axis), value);
+        }
+
+        let (input_device, input_axis) = input;
+        let input_range = self
+            .mapper
+            .real_device(input_device)
+            .unwrap()
+            .range(input_axis);
+        lines.push(String::new());
+        match input_range {
+            Some(input_range) => {
+                lines.push(format!(
+                    "{}:{} over {}:{}",
+                    device, axis, input_device, input_axis
+                ));
+                lines.append(&mut plot_curve(
+                    expr,
+                    input,
+                    input_range,
+                    &values,
+                    (config.min, config.max),
+                    (width.saturating_sub(10).max(10), CURVE_HEIGHT),
+                ));
+            }
+            None => lines.push(format!(
+                "The range of {}:{} is unknown while the device is disconnected",
+                input_device, input_axis
+            )),
+        }
+        return lines;
+    }
+}
+
+fn bar(value: Option<i32>, range: Option<(i32, i32)>) -> String {
+    let (min, max) = match range {
+        Some((min, max)) if max > min => (min as i64, max as i64),
+        _ => return String::new(),
+    };
+    let filled = value.map_or(0, |value| {
+        ((value as i64).clamp(min, max) - min) * BAR_WIDTH as i64 / (max - min)
+    }) as usize;
+    return format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+}
+
+/// Plots the output of the expression over the whole range of one input, with all other inputs
+/// taken from `values`. The current value of the input is marked by a vertical line.
+fn plot_curve(
+    expr: &AxisExpression,
+    input: &(String, Axis),
+    (input_min, input_max): (i32, i32),
+    values: &HashMap<(String, Axis), i32>,
+    (output_min, output_max): (i32, i32),
+    (width, height): (usize, usize),
+) -> Vec<String> {
+    let mut grid = vec![vec![' '; width]; height];
+    let mut values = values.clone();
+    let scale = |value: i64, min: i64, max: i64, steps: usize| {
+        if max <= min {
+            return 0;
+        }
+        return ((value - min) * (steps as i64 - 1) + (max - min) / 2) / (max - min);
+    };
+
+    if let Some(current) = values.get(input) {
+        let column = scale(*current as i64, input_min as i64, input_max as i64, width);
+        if (0..width as i64).contains(&column) {
+            for row in &mut grid {
+                row[column as usize] = '|';
+            }
+        }
+    }
+
+    let outputs: Vec<Option<i32>> = (0..width)
+        .map(|column| {
+            let input_value = input_min as i64
+                + (input_max as i64 - input_min as i64) * column as i64 / (width as i64 - 1).max(1);
+            values.insert(input.clone(), input_value as i32);
+            return expr.eval(&values).ok();
+        })
+        .collect();
+
+    for (column, output) in outputs.into_iter().enumerate() {
+        let output = match output {
+            Some(output) => output,
+            None => continue,
+        };
+        let row = scale(output as i64, output_min as i64, output_max as i64, height);
+        let (row, mark) = if row < 0 {
+            (0, 'v')
+        } else if row >= height as i64 {
+            (height - 1, '^')
+        } else {
+            (row as usize, '*')
+        };
+        grid[height - 1 - row][column] = mark;
+    }
+
+    let mut lines: Vec<String> = grid
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let label = match index {
+                0 => output_max.to_string(),
+                index if index == height - 1 => output_min.to_string(),
+                _ => String::new(),
+            };
+            format!("{:>8} {}", label, row.into_iter().collect::<String>())
+        })
+        .collect();
+    let (min_label, max_label) = (input_min.to_string(), input_max.to_string());
+    lines.push(format!(
+        "{:>8} {}{:>width$}",
+        "",
+        min_label,
+        max_label,
+        width = width.saturating_sub(min_label.len())
+    ));
+    return lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pimp_my_axis::config::Axis;
+    use pimp_my_axis::parse_expr;
+
+    use crate::tui::plot_curve;
+
+    #[test]
+    fn curve_marks_output_and_current_input() {
+        let expr = parse_expr("stick:X * 2 - 4").unwrap();
+        let input = ("stick".to_owned(), Axis::X);
+        let mut values = HashMap::new();
+        values.insert(input.clone(), 2);
+
+        let lines = plot_curve(&expr, &input, (0, 4), &values, (0, 4), (5, 5));
+        let rows: Vec<&str> = lines.iter().map(|line| &line[9..]).collect();
+        assert_eq!(
+            rows,
+            vec!["  | *", "  |  ", "  |* ", "  |  ", "vv*  ", "0   4"]
+        );
+    }
+}
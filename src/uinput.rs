@@ -0,0 +1,155 @@
+/*
+ * This file is part of Pimp-My-Axis.
+ *
+ * Pimp-My-Axis is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Pimp-My-Axis is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+use evdev_rs::{DeviceWrapper, UninitDevice};
+use evdev_rs::enums::EventCode;
+use evdev_rs::util::event_code_to_int;
+use evdev_sys as raw;
+use nix::libc;
+
+nix::ioctl_write_ptr_bad!(
+    set_phys,
+    nix::request_code_write!(b'U', 108, mem::size_of::<*const libc::c_char>()),
+    libc::c_char
+);
+
+/// A uinput device on a file descriptor of its own. Unlike with `evdev_rs::UInputDevice`, which
+/// opens `/dev/uinput` itself, the physical location can be set before the device is created.
+pub struct UinputDevice {
+    raw: *mut raw::libevdev_uinput,
+    /// Only closed once the device is destroyed.
+    _file: File,
+}
+
+impl UinputDevice {
+    /// Creates a uinput device with the name, IDs, properties and event codes of the given
+    /// libevdev device.
+    pub fn create(device: &UninitDevice, phys: Option<&str>) -> io::Result<UinputDevice> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/uinput")?;
+        if let Some(phys) = phys {
+            let phys = CString::new(phys)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            // SAFETY: `phys` is a NUL-terminated string which lives until the end of this block,
+            // while the kernel copies it during the ioctl, and `file` is open.
+            unsafe { set_phys(file.as_raw_fd(), phys.as_ptr()) }?;
+        }
+
+        let mut uinput_device = ptr::null_mut();
+        // SAFETY: `device` is borrowed for the duration of the call, which only reads it. `file`
+        // stays open as long as the returned device, as libevdev uses it without taking it over.
+        let result = unsafe {
+            raw::libevdev_uinput_create_from_device(
+                device.raw(),
+                file.as_raw_fd(),
+                &mut uinput_device,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(-result));
+        }
+        return Ok(UinputDevice {
+            raw: uinput_device,
+            _file: file,
+        });
+    }
+
+    /// Sends an event, which only takes effect with the next `SYN_REPORT`. The kernel sets its
+    /// time.
+    pub fn write_event(&self, event_code: &EventCode, value: i32) -> io::Result<()> {
+        let (event_type, code) = event_code_to_int(event_code);
+        // SAFETY: `raw` was created by a successful `create` and is only freed on drop.
+        let result = unsafe { raw::libevdev_uinput_write_event(self.raw, event_type, code, value) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(-result));
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        // SAFETY: `raw` was created by a successful `create` and is freed only here. Its file
+        // descriptor is still open, as fields are only dropped afterwards.
+        unsafe { raw::libevdev_uinput_destroy(self.raw) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+    use std::thread;
+    use std::time::Duration;
+
+    use evdev_rs::{DeviceWrapper, UninitDevice};
+    use evdev_rs::enums::{EV_KEY, EventCode};
+    use evdev_sys as raw;
+
+    use crate::uinput::UinputDevice;
+
+    nix::ioctl_read_buf!(get_phys, b'E', 0x07, u8);
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput"]
+    fn phys_is_set() {
+        let device = UninitDevice::new().unwrap();
+        device.set_name("Pimp-My-Axis phys test");
+        device
+            .enable_event_code(&EventCode::EV_KEY(EV_KEY::BTN_TRIGGER), None)
+            .unwrap();
+        let uinput_device = UinputDevice::create(&device, Some("pimp-my-axis/test0")).unwrap();
+
+        // SAFETY: `raw` belongs to the uinput device, which outlives the returned string, which is
+        // copied right away.
+        let devnode = unsafe {
+            let devnode = raw::libevdev_uinput_get_devnode(uinput_device.raw);
+            assert!(!devnode.is_null());
+            CStr::from_ptr(devnode).to_str().unwrap().to_owned()
+        };
+        // The device node may take a moment to appear.
+        let mut file = File::open(&devnode);
+        for _ in 0..50 {
+            if file.is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            file = File::open(&devnode);
+        }
+        let file = file.unwrap();
+
+        let mut phys = [0u8; 256];
+        // SAFETY: `file` is open and `phys` is writable for its whole length.
+        let length = unsafe { get_phys(file.as_raw_fd(), &mut phys) }.unwrap() as usize;
+        assert_eq!(
+            CStr::from_bytes_until_nul(&phys[..length])
+                .unwrap()
+                .to_str(),
+            Ok("pimp-my-axis/test0")
+        );
+    }
+}
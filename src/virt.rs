@@ -15,86 +15,680 @@
  * along with Pimp-My-Axis. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::rc::Rc;
+use std::io;
+use std::time::Duration;
 
-use log::info;
-use uinput::Event;
-use uinput::event::absolute::{Position, Wheel};
-use uinput::event::Absolute;
+use evdev_rs::{AbsInfo, DeviceWrapper, UninitDevice};
+use evdev_rs::enums::{EV_KEY, EV_SYN, EventCode, InputProp};
+use log::{info, warn};
 
-use crate::config::{Axis, AxisConfig, VirtDeviceConfig};
+use crate::config::{Axis, AxisConfig, HatConfig, OutOfRange, VirtDeviceConfig};
+use crate::error::{ConfigError, DeviceError, Error};
+use crate::real::{axis_to_event_code, event_code_to_axis};
+use crate::source::{AbsAxisInfo, DeviceSource};
+use crate::uinput::UinputDevice;
 
 pub struct VirtDevice {
     pub name: String,
     pub config: VirtDeviceConfig,
-    uinput_device: uinput::Device,
+    output: bool,
+    uinput_device: Option<UinputDevice>,
+    /// Whether `create` took over the capabilities of the passed through and cloned devices.
+    created: bool,
+    /// Event codes of the passthrough device which are forwarded, along with the value they rest at
+    /// while it is disconnected or the virtual device is parked, if any.
+    passthrough_codes: HashMap<EventCode, Option<i32>>,
+    values: HashMap<Axis, i32>,
+    /// Movement of the mouse axes which was too small to be sent so far.
+    mouse_remainders: HashMap<Axis, f64>,
+    /// Progress of the pulsed keys towards their next press, where 1 is due.
+    pulse_phases: HashMap<Axis, f64>,
+    /// Pulsed keys which are pressed, along with the time until they are released.
+    pulse_holds: HashMap<Axis, Duration>,
+    /// Whether events were sent since the last `SYN_REPORT`.
+    unsynced: bool,
+    /// How many `SYN_REPORT`s were sent, or would have been sent if there was output.
+    reports: u64,
+    /// The values the expressions of the axes can produce, as far as they are known.
+    expression_bounds: HashMap<Axis, (i64, i64)>,
+    out_of_range_counts: HashMap<Axis, u64>,
 }
 
-fn axis_to_event(axis: &Axis) -> Event {
-    return match axis {
-        Axis::X => Event::Absolute(Absolute::Position(Position::X)),
-        Axis::Y => Event::Absolute(Absolute::Position(Position::Y)),
-        Axis::Z => Event::Absolute(Absolute::Position(Position::Z)),
-        Axis::RX => Event::Absolute(Absolute::Position(Position::RX)),
-        Axis::RY => Event::Absolute(Absolute::Position(Position::RY)),
-        Axis::RZ => Event::Absolute(Absolute::Position(Position::RZ)),
-        Axis::Throttle => Event::Absolute(Absolute::Wheel(Wheel::Throttle)),
-        Axis::Rudder => Event::Absolute(Absolute::Wheel(Wheel::Rudder)),
-        Axis::Wheel => Event::Absolute(Absolute::Wheel(Wheel::Position)),
-        Axis::Gas => Event::Absolute(Absolute::Wheel(Wheel::Gas)),
-        Axis::Brake => Event::Absolute(Absolute::Wheel(Wheel::Brake)),
-    };
+fn check_axes(config: &VirtDeviceConfig) -> Result<(), String> {
+    if let Some(axis) = config.axes.keys().find(|axis| axis.is_relative()) {
+        return Err(format!("Relative axis {} belongs under mouse", axis));
+    }
+    if let Some(axis) = config.mouse.keys().find(|axis| !axis.is_relative()) {
+        return Err(format!("{} is not a relative axis", axis));
+    }
+    if let Some(key) = config
+        .pulses
+        .keys()
+        .find(|key| !key.is_key() || config.buttons.contains_key(key))
+    {
+        return Err(format!("{} is not a key or already used as a button", key));
+    }
+    if let Some((key, _)) = config
+        .pulses
+        .iter()
+        .find(|(_, pulse_config)| pulse_config.max <= 0 || pulse_config.rate <= 0.0)
+    {
+        return Err(format!("Pulsed key {} needs a positive max and rate", key));
+    }
+    if let Some((axis, _)) = config
+        .mouse
+        .iter()
+        .find(|(_, mouse_config)| mouse_config.max <= 0 || mouse_config.acceleration <= 0.0)
+    {
+        return Err(format!(
+            "Mouse axis {} needs a positive max and acceleration",
+            axis
+        ));
+    }
+    if let Some((axis, axis_config)) = config
+        .axes
+        .iter()
+        .find(|(_, axis_config)| axis_config.min > axis_config.max)
+    {
+        return Err(format!(
+            "Axis {} has a min of {} above its max of {}",
+            axis, axis_config.min, axis_config.max
+        ));
+    }
+    if let Some(axis) = config.axes.keys().find(|axis| axis.is_key()) {
+        return Err(format!("{} is a button and belongs under buttons", axis));
+    }
+    if let Some(axis) = config.buttons.keys().find(|axis| !axis.is_key()) {
+        return Err(format!("{} is not a button", axis));
+    }
+    for (hat, hat_config) in &config.hats {
+        let (x, y) = hat.axes();
+        if config.axes.contains_key(&x) || config.axes.contains_key(&y) {
+            return Err(format!("{} and {} are already used by a hat", x, y));
+        }
+        if let HatConfig::Stick { directions, .. } = hat_config {
+            if *directions != 4 && *directions != 8 {
+                return Err(format!(
+                    "A hat can point in 4 or 8 directions, not {}",
+                    directions
+                ));
+            }
+        }
+    }
+    return Ok(());
 }
 
 impl VirtDevice {
-    pub fn new(name: String, config: &VirtDeviceConfig) -> Result<VirtDevice, String> {
-        let mut builder = uinput::default()
-            .unwrap()
-            .name(&config.name)
-            .unwrap()
-            .vendor(config.vendor_id)
-            .product(config.product_id);
-
-        for (axis, axis_config) in &config.axes {
-            builder = builder
-                .event(axis_to_event(axis))
-                .unwrap()
-                .min(axis_config.min)
-                .max(axis_config.max);
+    /// Creates a virtual device along with its uinput device. If it passes through or clones a
+    /// real device, the uinput device is only created by `create` once the real device is
+    /// available.
+    pub fn new(name: String, config: &VirtDeviceConfig) -> Result<VirtDevice, Error> {
+        let mut device = VirtDevice::new_without_output(name, config)?;
+        device.output = true;
+        if device.sources().is_empty() {
+            device.create(&HashMap::new())?;
         }
+        return Ok(device);
+    }
 
-        let uinput_device = builder.create().map_err(|err| err.to_string())?;
-
-        info!("Created uinput virtual device '{}'", config.name);
+    /// Creates a virtual device which only keeps track of its values, without a uinput device.
+    pub fn new_without_output(
+        name: String,
+        config: &VirtDeviceConfig,
+    ) -> Result<VirtDevice, ConfigError> {
+        check_axes(config).map_err(|message| {
+            ConfigError::invalid(format!("Virtual device '{}'", name), message)
+        })?;
 
         return Ok(VirtDevice {
             name,
-            uinput_device,
             config: config.clone(),
+            output: false,
+            uinput_device: None,
+            created: false,
+            passthrough_codes: HashMap::new(),
+            values: HashMap::new(),
+            mouse_remainders: HashMap::new(),
+            pulse_phases: HashMap::new(),
+            pulse_holds: HashMap::new(),
+            unsynced: false,
+            reports: 0,
+            expression_bounds: HashMap::new(),
+            out_of_range_counts: HashMap::new(),
+        });
+    }
+
+    /// The real devices which are passed through or cloned.
+    pub fn sources(&self) -> Vec<&str> {
+        let mut sources: Vec<&str> = self
+            .config
+            .passthrough
+            .iter()
+            .map(|passthrough| passthrough.device.as_str())
+            .chain(self.config.clone.iter().map(|clone| clone.device.as_str()))
+            .collect();
+        sources.dedup();
+        return sources;
+    }
+
+    /// The real devices which need to be available for `create`, if it has not been called yet.
+    pub fn pending_sources(&self) -> Vec<&str> {
+        if self.created {
+            return Vec::new();
+        }
+        return self.sources();
+    }
+
+    /// Takes over the capabilities of the passed through and cloned devices, which must be
+    /// contained in `sources`, and creates the uinput device with them and the configured axes if
+    /// there is output. Values written so far are sent right away.
+    pub fn create(
+        &mut self,
+        sources: &HashMap<&str, &dyn DeviceSource>,
+    ) -> Result<(), DeviceError> {
+        if let Some(config) = &self.config.passthrough {
+            self.passthrough_codes =
+                passthrough_codes(&self.config, sources[config.device.as_str()]);
+        }
+        if self.output {
+            self.create_uinput_device(sources)
+                .map_err(|source| self.error(source))?;
+        }
+        self.created = true;
+        return Ok(());
+    }
+
+    fn create_uinput_device(
+        &mut self,
+        sources: &HashMap<&str, &dyn DeviceSource>,
+    ) -> io::Result<()> {
+        let device = UninitDevice::new()
+            .ok_or_else(|| io::Error::other("Unable to create a libevdev device"))?;
+        device.set_name(&self.config.name);
+        device.set_vendor_id(self.config.vendor_id);
+        device.set_product_id(self.config.product_id);
+        let mut phys = None;
+
+        if let Some(clone) = &self.config.clone {
+            let source = sources[clone.device.as_str()];
+            let identity = source.identity().unwrap_or_default();
+            device.set_name(&format!("{}{}", identity.name, clone.name_suffix));
+            device.set_vendor_id(identity.vendor_id);
+            device.set_product_id(identity.product_id);
+            device.set_bustype(identity.bustype);
+            device.set_version(identity.version);
+            phys = identity.phys;
+            for property in &identity.properties {
+                enable_property(&device, property)?;
+            }
+            for code in source.supported_codes() {
+                enable_event_code(&device, &code, source.abs_info(&code))?;
+            }
+        }
+
+        if let Some(bustype) = self.config.bustype {
+            device.set_bustype(bustype.code());
+        }
+        if let Some(version) = self.config.version {
+            device.set_version(version);
+        }
+        if let Some(config_phys) = &self.config.phys {
+            phys = Some(config_phys.clone());
+        }
+        for property in &self.config.properties {
+            enable_property(&device, &property.0)?;
+        }
+
+        if let Some(config) = &self.config.passthrough {
+            let source = sources[config.device.as_str()];
+            for code in self.passthrough_codes.keys() {
+                enable_event_code(&device, code, source.abs_info(code))?;
+            }
+        }
+
+        for (axis, axis_config) in &self.config.axes {
+            let abs_info = AbsAxisInfo {
+                value: 0,
+                minimum: axis_config.min,
+                maximum: axis_config.max,
+                fuzz: axis_config.fuzz,
+                flat: axis_config.flat,
+                resolution: axis_config.resolution,
+            };
+            enable_event_code(&device, &axis_to_event_code(axis), Some(abs_info))?;
+        }
+        for hat in self.config.hats.keys() {
+            let (x, y) = hat.axes();
+            for axis in [x, y] {
+                let abs_info = AbsAxisInfo {
+                    value: 0,
+                    minimum: -1,
+                    maximum: 1,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution: 0,
+                };
+                enable_event_code(&device, &axis_to_event_code(&axis), Some(abs_info))?;
+            }
+        }
+        for code in key_and_rel_codes(&self.config) {
+            enable_event_code(&device, &code, None)?;
+        }
+
+        let uinput_device = UinputDevice::create(&device, phys.as_deref()).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Unable to create uinput device: {}", err),
+            )
+        })?;
+        info!(
+            "Created uinput virtual device '{}'",
+            device.name().unwrap_or_default()
+        );
+
+        for (axis, value) in self.values.iter().filter(|(axis, _)| !self.is_rate(axis)) {
+            write_event(&uinput_device, &axis_to_event_code(axis), *value)?;
+        }
+        write_event(&uinput_device, &EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)?;
+
+        self.uinput_device = Some(uinput_device);
+        return Ok(());
+    }
+
+    /// How many `SYN_REPORT`s were sent, each of which applies the events sent before it at once.
+    pub fn report_count(&self) -> u64 {
+        return self.reports;
+    }
+
+    /// The value last written to the given axis.
+    pub fn value(&self, axis: &Axis) -> Option<i32> {
+        return self.values.get(axis).copied();
+    }
+
+    /// Writes a value to an axis or button, which takes effect on the next `sync`. Buttons are
+    /// pressed by any value other than 0 and only send an event when their state changes.
+    pub fn write(&mut self, axis: &Axis, value: i32) -> Result<(), DeviceError> {
+        return match self.fit_range(axis, value) {
+            Some(value) => self.write_value(axis, value),
+            None => Ok(()),
+        };
+    }
+
+    fn write_value(&mut self, axis: &Axis, value: i32) -> Result<(), DeviceError> {
+        let value = if axis.is_key() && !self.is_rate(axis) {
+            (value != 0) as i32
+        } else {
+            value
+        };
+        let previous = self.values.insert(*axis, value);
+        if self.is_rate(axis) || axis.is_key() && previous == Some(value) {
+            return Ok(());
+        }
+        return self.send(&axis_to_event_code(axis), value);
+    }
+
+    /// Moves all axes to their park value, releases all buttons, centers the hats and passed
+    /// through axes and stops the mouse axes and pulsed keys. Takes effect on the next `sync`.
+    /// Every axis is tried even if some fail, and the first error is returned while the others are
+    /// logged.
+    pub fn park(&mut self) -> Result<(), DeviceError> {
+        let mut values: Vec<(Axis, i32)> = self
+            .config
+            .axes
+            .iter()
+            .map(|(axis, axis_config)| (*axis, axis_config.park_value()))
+            .collect();
+        let hat_axes = self.config.hats.keys().flat_map(|hat| {
+            let (x, y) = hat.axes();
+            return [x, y];
         });
+        let resting = self
+            .config
+            .buttons
+            .keys()
+            .copied()
+            .chain(hat_axes)
+            .chain(self.config.mouse.keys().copied())
+            .chain(self.config.pulses.keys().copied());
+        values.extend(resting.map(|axis| (axis, 0)));
+
+        let mut errors = Vec::new();
+        for (axis, value) in values {
+            if let Err(err) = self.write_value(&axis, value) {
+                errors.push((axis.to_string(), err));
+            }
+        }
+        let held: Vec<Axis> = self.pulse_holds.drain().map(|(key, _)| key).collect();
+        for key in held {
+            if let Err(err) = self.send(&axis_to_event_code(&key), 0) {
+                errors.push((key.to_string(), err));
+            }
+        }
+        self.park_passthrough_codes(&mut errors);
+        return first_error(errors);
+    }
+
+    /// Moves the passed through axes to their rest value and releases the passed through buttons,
+    /// e.g. while the real device they come from is disconnected. Takes effect on the next `sync`.
+    pub fn park_passthrough(&mut self) -> Result<(), DeviceError> {
+        let mut errors = Vec::new();
+        self.park_passthrough_codes(&mut errors);
+        return first_error(errors);
+    }
+
+    fn park_passthrough_codes(&mut self, errors: &mut Vec<(String, DeviceError)>) {
+        let passed_through: Vec<(EventCode, i32)> = self
+            .passthrough_codes
+            .iter()
+            .filter_map(|(code, rest_value)| Some((*code, (*rest_value)?)))
+            .collect();
+        for (code, value) in passed_through {
+            if let Err(err) = self.send_passed_through(&code, value) {
+                errors.push((code.to_string(), err));
+            }
+        }
+    }
+
+    /// Applies the out of range policy of an axis to a value of its expression. Values out of range
+    /// are counted, and logged the first time. Returns None if the value is dropped, which is only
+    /// counted as well.
+    fn fit_range(&mut self, axis: &Axis, value: i32) -> Option<i32> {
+        let axis_config = match self.config.axes.get(axis) {
+            Some(axis_config) => axis_config,
+            None => return Some(value),
+        };
+        let bounds = self.expression_bounds.get(axis).copied();
+        let expected = match (axis_config.out_of_range, bounds) {
+            (OutOfRange::Scale, Some(bounds)) => bounds,
+            _ => (axis_config.min as i64, axis_config.max as i64),
+        };
+
+        if (value as i64) < expected.0 || (value as i64) > expected.1 {
+            let count = self.out_of_range_counts.entry(*axis).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                warn!(
+                    "Virtual axis {}:{} got the value {} outside of {}..{}, further ones are only counted",
+                    self.name, axis, value, expected.0, expected.1
+                );
+            }
+        }
+
+        return axis_config
+            .out_of_range
+            .apply(value, axis_config.min, axis_config.max, bounds);
+    }
+
+    /// Sets the values the expression of an axis can produce, which `scale` maps onto its range.
+    pub fn set_expression_bounds(&mut self, axis: &Axis, bounds: Option<(i64, i64)>) {
+        match bounds {
+            Some(bounds) => self.expression_bounds.insert(*axis, bounds),
+            None => self.expression_bounds.remove(axis),
+        };
+    }
+
+    /// How many values of the expression of an axis were out of range so far.
+    pub fn out_of_range_count(&self, axis: &Axis) -> u64 {
+        return self.out_of_range_counts.get(axis).copied().unwrap_or(0);
+    }
+
+    /// Whether the values of the axis are a speed or rate, which only takes effect on `tick`.
+    fn is_rate(&self, axis: &Axis) -> bool {
+        return self.config.mouse.contains_key(axis) || self.config.pulses.contains_key(axis);
+    }
+
+    /// Moves the mouse axes as far as they went at their current speed and presses the pulsed
+    /// keys which are due in the given time.
+    pub fn tick(&mut self, elapsed: Duration) -> Result<(), DeviceError> {
+        let mut moves = Vec::new();
+        for (axis, mouse_config) in &self.config.mouse {
+            let speed = mouse_config.speed(self.values.get(axis).copied().unwrap_or(0));
+            let remainder = self.mouse_remainders.entry(*axis).or_insert(0.0);
+            if speed == 0.0 {
+                *remainder = 0.0;
+                continue;
+            }
+
+            let distance = *remainder + speed * elapsed.as_secs_f64();
+            *remainder = distance.fract();
+            if distance.trunc() != 0.0 {
+                moves.push((*axis, distance.trunc() as i32));
+            }
+        }
+        for (axis, distance) in moves {
+            self.send(&axis_to_event_code(&axis), distance)?;
+        }
+
+        let mut changes = Vec::new();
+        for (key, pulse_config) in &self.config.pulses {
+            let rate = pulse_config.rate(self.values.get(key).copied().unwrap_or(0));
+            // A key which starts pulsing is pressed right away.
+            let phase = self.pulse_phases.entry(*key).or_insert(1.0);
+            if rate == 0.0 {
+                *phase = 1.0;
+            } else {
+                *phase += rate * elapsed.as_secs_f64();
+            }
+            let due = rate != 0.0 && *phase >= 1.0;
+
+            match self.pulse_holds.get_mut(key) {
+                // A key held for longer than the time between presses is released early, and
+                // pressed again on the next tick.
+                Some(remaining) => {
+                    *remaining = remaining.saturating_sub(elapsed);
+                    if remaining.is_zero() || due {
+                        self.pulse_holds.remove(key);
+                        changes.push((*key, 0));
+                    }
+                }
+                None if due => {
+                    *phase = phase.fract();
+                    self.pulse_holds
+                        .insert(*key, Duration::from_millis(pulse_config.hold));
+                    changes.push((*key, 1));
+                }
+                None => {}
+            }
+        }
+        for (key, value) in changes {
+            self.send(&axis_to_event_code(&key), value)?;
+        }
+        return Ok(());
     }
 
-    pub fn write(&mut self, axis: &Axis, value: i32) -> Result<(), String> {
-        self.uinput_device
-            .send(axis_to_event(axis), value)
-            .map_err(|err| err.to_string())?;
-        self.uinput_device
-            .synchronize()
-            .map_err(|err| err.to_string())
+    /// Forwards an event of a real device if this device passes it through.
+    pub fn forward(
+        &mut self,
+        device: &str,
+        event_code: &EventCode,
+        value: i32,
+    ) -> Result<(), DeviceError> {
+        let passes_through = self
+            .config
+            .passthrough
+            .as_ref()
+            .is_some_and(|passthrough| passthrough.device == device);
+        if !passes_through || !self.passthrough_codes.contains_key(event_code) {
+            return Ok(());
+        }
+        return self.send_passed_through(event_code, value);
+    }
+
+    /// Sends an event of the passthrough device, keeping track of its value if it belongs to an
+    /// axis or button.
+    fn send_passed_through(
+        &mut self,
+        event_code: &EventCode,
+        value: i32,
+    ) -> Result<(), DeviceError> {
+        if let Some(axis) = event_code_to_axis(event_code) {
+            self.values.insert(axis, value);
+        }
+        return self.send(event_code, value);
+    }
+
+    fn send(&mut self, event_code: &EventCode, value: i32) -> Result<(), DeviceError> {
+        if let Some(uinput_device) = &self.uinput_device {
+            write_event(uinput_device, event_code, value).map_err(|source| self.error(source))?;
+        }
+        self.unsynced = true;
+        return Ok(());
+    }
+
+    /// Sends a `SYN_REPORT` if any events were sent since the last one, such that everything
+    /// written in the meantime is seen as a single frame.
+    pub fn sync(&mut self) -> Result<(), DeviceError> {
+        if !self.unsynced {
+            return Ok(());
+        }
+        self.unsynced = false;
+        self.reports += 1;
+        return match &self.uinput_device {
+            Some(uinput_device) => {
+                write_event(uinput_device, &EventCode::EV_SYN(EV_SYN::SYN_REPORT), 0)
+                    .map_err(|source| self.error(source))
+            }
+            None => Ok(()),
+        };
+    }
+
+    fn error(&self, source: io::Error) -> DeviceError {
+        return DeviceError::Uinput {
+            device: self.name.clone(),
+            source,
+        };
+    }
+}
+
+/// The buttons, relative axes and keys of the device, apart from those of passed through and
+/// cloned devices. A device moving `REL_X` or `REL_Y` also gets the mouse buttons, since udev only
+/// tags it as a mouse with those, and desktops ignore it otherwise.
+fn key_and_rel_codes(config: &VirtDeviceConfig) -> Vec<EventCode> {
+    let mut codes: Vec<EventCode> = config
+        .buttons
+        .keys()
+        .chain(config.mouse.keys())
+        .chain(config.pulses.keys())
+        .map(axis_to_event_code)
+        .collect();
+    if config.mouse.contains_key(&Axis::RelX) || config.mouse.contains_key(&Axis::RelY) {
+        for button in [EV_KEY::BTN_LEFT, EV_KEY::BTN_RIGHT, EV_KEY::BTN_MIDDLE] {
+            let code = EventCode::EV_KEY(button);
+            if !codes.contains(&code) {
+                codes.push(code);
+            }
+        }
     }
+    return codes;
+}
+
+/// Returns the first of the errors, if any, and logs the others.
+fn first_error(errors: Vec<(String, DeviceError)>) -> Result<(), DeviceError> {
+    let mut errors = errors.into_iter();
+    return match errors.next() {
+        Some((_, first)) => {
+            for (what, err) in errors {
+                warn!("Unable to park {} of virtual device: {}", what, err);
+            }
+            Err(first)
+        }
+        None => Ok(()),
+    };
+}
+
+/// The event codes of the passthrough device which are forwarded, which excludes those of the
+/// configured axes, along with the value they rest at, if any. Unless configured otherwise, axes
+/// rest at their center and buttons are released.
+fn passthrough_codes(
+    config: &VirtDeviceConfig,
+    source: &dyn DeviceSource,
+) -> HashMap<EventCode, Option<i32>> {
+    let passthrough = match &config.passthrough {
+        Some(passthrough) => passthrough,
+        None => return HashMap::new(),
+    };
+    let axis_codes: Vec<EventCode> = config
+        .axes
+        .keys()
+        .chain(config.buttons.keys())
+        .chain(config.mouse.keys())
+        .chain(config.pulses.keys())
+        .copied()
+        .chain(config.hats.keys().flat_map(|hat| {
+            let (x, y) = hat.axes();
+            [x, y]
+        }))
+        .map(|axis| axis_to_event_code(&axis))
+        .collect();
+    return source
+        .supported_codes()
+        .into_iter()
+        .filter(|code| passthrough.includes(code) && !axis_codes.contains(code))
+        .map(|code| {
+            let center_or_released = match (&code, source.abs_info(&code)) {
+                (EventCode::EV_ABS(_), Some(info)) => {
+                    Some(((info.minimum as i64 + info.maximum as i64) / 2) as i32)
+                }
+                (EventCode::EV_KEY(_), _) => Some(0),
+                _ => None,
+            };
+            return (code, passthrough.park_value(&code).or(center_or_released));
+        })
+        .collect();
+}
+
+fn enable_event_code(
+    device: &UninitDevice,
+    event_code: &EventCode,
+    abs_info: Option<AbsAxisInfo>,
+) -> io::Result<()> {
+    // evdev-rs would pass a pointer to a temporary when given an `AbsInfo`, so pass the raw struct.
+    let result = match abs_info {
+        Some(abs_info) if matches!(event_code, EventCode::EV_ABS(_)) => {
+            let abs_info = AbsInfo {
+                value: abs_info.value,
+                minimum: abs_info.minimum,
+                maximum: abs_info.maximum,
+                fuzz: abs_info.fuzz,
+                flat: abs_info.flat,
+                resolution: abs_info.resolution,
+            };
+            device.enable_event_code(event_code, Some(&abs_info.as_raw()))
+        }
+        _ => device.enable_event_code(event_code, None),
+    };
+    return result.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Unable to enable {:?}: {}", event_code, err),
+        )
+    });
+}
+
+fn enable_property(device: &UninitDevice, property: &InputProp) -> io::Result<()> {
+    return device.enable_property(property).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Unable to enable {:?}: {}", property, err),
+        )
+    });
+}
+
+fn write_event(uinput_device: &UinputDevice, event_code: &EventCode, value: i32) -> io::Result<()> {
+    return uinput_device.write_event(event_code, value);
 }
 
 #[derive(Clone)]
 pub struct VirtAxis {
-    pub device: Rc<RefCell<VirtDevice>>,
+    pub device: String,
     pub axis: Axis,
     pub config: AxisConfig,
 }
 
 impl VirtAxis {
-    pub fn new(device: Rc<RefCell<VirtDevice>>, axis: Axis, config: AxisConfig) -> VirtAxis {
+    pub fn new(device: String, axis: Axis, config: AxisConfig) -> VirtAxis {
         return VirtAxis {
             device,
             axis,
@@ -103,22 +697,114 @@ impl VirtAxis {
     }
 }
 
-impl PartialEq for VirtAxis {
-    fn eq(&self, other: &Self) -> bool {
-        return self.axis == other.axis
-            && self.config.expr == other.config.expr
-            && Rc::ptr_eq(&self.device, &other.device);
+impl Display for VirtAxis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}:{}", self.device, self.axis))
     }
 }
 
-impl Eq for VirtAxis {}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
 
-impl Display for VirtAxis {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(
-            "{}:{:?}",
-            self.device.borrow().name,
-            self.axis
-        ))
+    use evdev_rs::enums::{EV_KEY, EV_REL, EventCode};
+
+    use crate::config::{Axis, VirtDeviceConfig};
+    use crate::virt::{key_and_rel_codes, VirtDevice};
+
+    #[test]
+    fn mouse_gets_buttons() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "{ mouse: { REL_X: { expr: \"stick:RX\", max: 1000 } }, buttons: { BTN_LEFT: \"stick:BTN_TRIGGER\" } }",
+        )
+        .unwrap();
+        let mut codes = key_and_rel_codes(&config);
+        codes.sort_by_key(|code| format!("{:?}", code));
+        assert_eq!(
+            codes,
+            vec![
+                EventCode::EV_KEY(EV_KEY::BTN_LEFT),
+                EventCode::EV_KEY(EV_KEY::BTN_MIDDLE),
+                EventCode::EV_KEY(EV_KEY::BTN_RIGHT),
+                EventCode::EV_REL(EV_REL::REL_X),
+            ]
+        );
+
+        let config: VirtDeviceConfig =
+            serde_yaml::from_str("mouse: { REL_WHEEL: { expr: \"stick:RY\", max: 1000 } }")
+                .unwrap();
+        assert_eq!(
+            key_and_rel_codes(&config),
+            vec![EventCode::EV_REL(EV_REL::REL_WHEEL)]
+        );
+    }
+
+    #[test]
+    fn pulsed_key_is_held() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "pulses: { KEY_EQUAL: { expr: \"stick:RY\", max: 100, rate: 5, hold: 50 } }",
+        )
+        .unwrap();
+        let key = Axis::Key(EV_KEY::KEY_EQUAL);
+        let mut device = VirtDevice::new_without_output("keys".to_owned(), &config).unwrap();
+        let tick = |device: &mut VirtDevice, millis| {
+            device.tick(Duration::from_millis(millis)).unwrap();
+            return device.pulse_holds.contains_key(&key);
+        };
+
+        device.write(&key, 100).unwrap();
+        assert!(tick(&mut device, 10));
+        assert!(tick(&mut device, 40));
+        assert!(!tick(&mut device, 10));
+        assert!(!tick(&mut device, 100));
+        assert!(tick(&mut device, 50));
+
+        device.park().unwrap();
+        assert!(device.pulse_holds.is_empty());
+    }
+
+    #[test]
+    fn park_moves_every_axis_to_rest() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            r#"{
+                axes: {
+                    X: { min: -100, max: 101, expr: "stick:X" },
+                    Y: { min: 0, max: 255, expr: "stick:Y", park: 255 }
+                },
+                buttons: { BTN_TRIGGER: "stick:BTN_TRIGGER" },
+                hats: { HAT0: { x: "stick:RX", y: "stick:RY", threshold: 10 } }
+            }"#,
+        )
+        .unwrap();
+        let mut device = VirtDevice::new_without_output("out".to_owned(), &config).unwrap();
+        let button = Axis::Key(EV_KEY::BTN_TRIGGER);
+
+        device.write(&Axis::X, 90).unwrap();
+        device.write(&Axis::Y, 3).unwrap();
+        device.write(&button, 1).unwrap();
+        device.write(&Axis::Hat0X, 1).unwrap();
+        device.write(&Axis::Hat0Y, -1).unwrap();
+        device.park().unwrap();
+
+        assert_eq!(device.value(&Axis::X), Some(0));
+        assert_eq!(device.value(&Axis::Y), Some(255));
+        assert_eq!(device.value(&button), Some(0));
+        assert_eq!(device.value(&Axis::Hat0X), Some(0));
+        assert_eq!(device.value(&Axis::Hat0Y), Some(0));
+    }
+
+    #[test]
+    fn out_of_range_values_are_dropped_quietly() {
+        let config: VirtDeviceConfig = serde_yaml::from_str(
+            "axes: { X: { min: 0, max: 10, expr: \"stick:X\", out_of_range: error } }",
+        )
+        .unwrap();
+        let mut device = VirtDevice::new_without_output("out".to_owned(), &config).unwrap();
+
+        device.write(&Axis::X, 5).unwrap();
+        device.write(&Axis::X, 20).unwrap();
+        device.write(&Axis::X, -1).unwrap();
+        assert_eq!(device.value(&Axis::X), Some(5));
+        assert_eq!(device.out_of_range_count(&Axis::X), 2);
     }
 }